@@ -0,0 +1,161 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+pub const CACHE_FILE: &str = ".makegen-cache";
+
+// Bump this whenever the way files are parsed (or hashed) changes so that stale caches are discarded
+const CACHE_VERSION: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub hash: u64,
+    pub has_main: bool,
+    pub include_files: Vec<String>,
+    pub dlls: Vec<String>,
+}
+
+// Caches the result of parsing each file keyed on a hash of its contents,
+// so touching a file without changing it doesn't make it parse again
+#[derive(Debug)]
+pub struct ParseCache {
+    fingerprint: u64,
+    entries: HashMap<String, CacheEntry>,
+    hits: usize,
+}
+
+impl ParseCache {
+    pub fn new(fingerprint: u64) -> Self {
+        Self {
+            fingerprint,
+            entries: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    // Loads the cache from the given file. A missing, corrupt or outdated cache file results in an empty cache
+    pub fn load(path: &Path, fingerprint: u64) -> Self {
+        let mut cache = Self::new(fingerprint);
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return cache,
+        };
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(&header(fingerprint)) {
+            return cache;
+        }
+
+        for line in lines {
+            match parse_entry(line) {
+                Some((filename, entry)) => {
+                    cache.entries.insert(filename, entry);
+                }
+                None => return Self::new(fingerprint),
+            }
+        }
+
+        cache
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = header(self.fingerprint);
+        contents.push('\n');
+
+        let mut filenames: Vec<_> = self.entries.keys().collect();
+        filenames.sort();
+
+        for filename in filenames {
+            let entry = &self.entries[filename];
+            contents.push_str(&format!(
+                "{}\t{:x}\t{}\t{}\t{}\n",
+                filename,
+                entry.hash,
+                entry.has_main as u8,
+                entry.include_files.join(";"),
+                entry.dlls.join(";")
+            ));
+        }
+
+        fs::write(path, contents)
+    }
+
+    // Returns the cached entry of the file if its contents haven't changed
+    // and all of the files it includes still exist
+    pub fn lookup(&mut self, root_dir: &Path, filename: &str, hash: u64) -> Option<CacheEntry> {
+        let entry = self.entries.get(filename)?;
+
+        if entry.hash != hash
+            || !entry
+                .include_files
+                .iter()
+                .all(|f| root_dir.join(f).exists())
+        {
+            return None;
+        }
+
+        self.hits += 1;
+        Some(entry.clone())
+    }
+
+    pub fn insert(&mut self, filename: String, entry: CacheEntry) {
+        self.entries.insert(filename, entry);
+    }
+
+    #[cfg(test)]
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+// 64-bit FNV-1a. Unlike the standard library's hasher it's specified, so the hashes
+// stored in the cache stay valid across Rust versions
+pub fn content_hash(contents: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    contents.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+fn header(fingerprint: u64) -> String {
+    format!("makegen-cache {} {:x}", CACHE_VERSION, fingerprint)
+}
+
+fn parse_entry(line: &str) -> Option<(String, CacheEntry)> {
+    let split_list = |list: &str| {
+        list.split(';')
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+
+    let mut fields = line.split('\t');
+    let filename = fields.next()?.to_owned();
+    let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let has_main = fields.next()? == "1";
+    let include_files = split_list(fields.next()?);
+    let dlls = split_list(fields.next()?);
+
+    Some((
+        filename,
+        CacheEntry {
+            hash,
+            has_main,
+            include_files,
+            dlls,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_is_fnv1a() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(content_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -1,39 +1,212 @@
-use clap::ArgMatches;
-use std::collections::HashSet;
+use crate::{
+    filename_utils::{CPP_EXTENSIONS, MIXED_EXTENSION},
+    parser::detect_extension,
+};
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Overrides the location of the system-wide link map
+const LINK_MAP_ENV: &str = "MAKEGEN_LINK_MAP";
+const SYSTEM_LINK_MAP: &str = "/etc/makegen/links.toml";
+
+// The project's configuration, read from the root directory
+pub const CONFIG_FILE: &str = "makegen.toml";
 
 pub struct Cli<'cli> {
     pub main_file: &'cli str,
     pub compiler: &'cli str,
     pub extension: &'cli str,
     pub binary: &'cli str,
-    pub standard: &'cli str,
+    // No standard is passed for freestanding builds, unless one is given explicitly
+    pub standard: Option<&'cli str>,
     pub opt_level: &'cli str,
     pub tests: HashSet<&'cli str>,
     pub benchmarks: HashSet<&'cli str>,
     pub examples: HashSet<&'cli str>,
+    pub pkg_config: Vec<String>,
+    pub explain_includes: bool,
+    pub self_dep: bool,
+    pub test_framework: Option<&'cli str>,
+    pub obj_suffix: String,
+    pub test_keep_going: bool,
+    pub whole_archives: Vec<&'cli str>,
+    pub hash_cache: bool,
+    pub emit_dot: bool,
+    pub dep_graph_target: bool,
+    pub modules: bool,
+    pub warn_missing: bool,
+    pub strict_includes: bool,
+    // Pairs of binary names and the extra link flags for them
+    pub binary_lflags: Vec<(&'cli str, &'cli str)>,
+    pub lint_target: bool,
+    pub linter: &'cli str,
+    pub toolchain_prefix: &'cli str,
+    pub objcopy: Vec<&'cli str>,
+    pub summary_json: Option<&'cli str>,
+    pub normalize_paths: bool,
+    pub iquote: Vec<&'cli str>,
+    pub fuzz: Option<&'cli str>,
+    pub exclude_files: Vec<&'cli str>,
+    pub relocatable: bool,
+    pub pie: bool,
+    pub auto_deps: bool,
+    pub depfile_dir: Option<&'cli str>,
+    pub generated: Vec<(&'cli str, &'cli str)>,
+    pub source_roots: Vec<&'cli str>,
+    pub rpaths: Vec<&'cli str>,
+    pub programs: Vec<(&'cli str, &'cli str)>,
+    pub dist: bool,
+    pub archive_format: &'cli str,
+    pub include_macros: Vec<(&'cli str, &'cli str)>,
+    pub compile_flags: bool,
+    pub sort_by_deps: bool,
+    pub touch_only: bool,
+    // Maps system headers to the libraries to link against, on top of the builtin mappings
+    pub link_map: HashMap<String, String>,
+    pub print_deps: Option<&'cli str>,
+    pub format: &'cli str,
+    pub skip_included_sources: bool,
+    pub tags_target: bool,
+    pub ctags: &'cli str,
+    // Static libraries depending on each other, linked inside a single group each
+    pub link_groups: Vec<Vec<&'cli str>>,
+    pub mirror_dirs: bool,
+    pub require_guards: bool,
+    pub build_profiles: bool,
+    // The number of headers a translation unit may pull in before it is reported
+    pub warn_long_compile_units: Option<usize>,
+    pub linker: Option<&'cli str>,
+    // Either git or the file holding the version
+    pub version_from: Option<&'cli str>,
+    pub static_lib: Option<&'cli str>,
+    pub emit_bear_db: bool,
+    pub flat: bool,
+    pub lazy_libm: bool,
+    pub freestanding: bool,
+    pub detect_by_content: bool,
+    pub max_warnings: Option<usize>,
+    pub dump_partitions: bool,
+    pub lto: bool,
+    pub print_plan: bool,
+    pub compiler_dep: bool,
+    pub object_recipes: Vec<(&'cli str, &'cli str)>,
+    pub include_mks: Vec<&'cli str>,
+    pub link_jobs: Option<usize>,
+    pub echo_includes: bool,
+    pub test_runner: Option<&'cli str>,
+    pub explain: Option<&'cli str>,
+    pub canonical_headers: bool,
+    // C sources are compiled as C next to the C++ ones
+    pub mixed: bool,
+    pub include_dirs: Vec<&'cli str>,
+    pub excludes: Vec<&'cli str>,
+    pub cflags: Option<&'cli str>,
+    pub libs: Vec<&'cli str>,
+    pub ldflags: Option<&'cli str>,
+    // NAME or NAME=VALUE
+    pub defines: Vec<&'cli str>,
+    pub debug_switch: bool,
+    pub sanitizers: bool,
+    pub format_targets: bool,
+    pub tidy_target: bool,
+    // The artifacts the install target installs, empty for the default ones
+    pub install: Option<Vec<&'cli str>>,
+    pub project_version: &'cli str,
+    // The kind of library the project is built as, with the programs linking against it
+    pub lib: Option<&'cli str>,
+    pub valgrind: bool,
+    // Whether the dependencies of the objects are makegen's or the compiler's only
+    pub depmode: &'cli str,
+    pub compact: bool,
+    // The standard of the C sources of mixed projects
+    pub c_standard: Option<&'cli str>,
+    // The arguments makegen was invoked with (excluding the program name)
+    pub invocation: Vec<String>,
 }
 
 impl<'cli> Cli<'cli> {
-    pub fn from_matches(matches: &'cli ArgMatches<'cli>) -> Result<Self, &'static str> {
-        let extension = matches
-            .value_of("extension")
-            .ok_or("You must provide and file extension to search for")?;
+    // The directories quoted includes are looked up in after the including file's one, in the compiler's order
+    pub fn quote_search_dirs(&self) -> Vec<&'cli str> {
+        self.iquote
+            .iter()
+            .chain(&self.include_dirs)
+            .copied()
+            .collect()
+    }
+
+    // The extension of the files compiled to objects, which covers both languages in mixed projects
+    pub fn source_extension(&self) -> &'cli str {
+        if self.mixed {
+            MIXED_EXTENSION
+        } else {
+            self.extension
+        }
+    }
+
+    pub fn from_matches(
+        matches: &'cli ArgMatches<'cli>,
+        root_dir: &Path,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mixed = matches.is_present("mixed");
+        let excludes: Vec<_> = matches
+            .values_of("exclude")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        // cpp stands for every C++ source extension, so the other ones are accepted for it as well
+        let extension = match matches.value_of("extension") {
+            Some(extension) if CPP_EXTENSIONS.contains(&extension) => "cpp",
+            Some(_) if mixed => {
+                return Err(
+                    "Mixed projects (--mixed) are C++ projects, their extension is cpp".into(),
+                )
+            }
+            Some(extension) => extension,
+            None if mixed => "cpp",
+            None => detect_extension(root_dir, &excludes)?,
+        };
 
         if extension != "c" && extension != "cpp" {
-            return Err("Only C or C++ files are allowed (extension should be either c or cpp)");
+            return Err(
+                "Only C or C++ files are allowed (extension should be either c or cpp, which covers cc, cxx and C as well)"
+                    .into(),
+            );
         }
 
         let binary = matches
             .value_of("bin")
             .ok_or("You must provide a name for your executable")?;
 
-        let main_file = matches
-            .value_of("main_file")
-            .ok_or("You must provide the main source file")?;
+        let is_c = extension == "c";
+
+        let main_file =
+            matches
+                .value_of("main_file")
+                .unwrap_or(if is_c { "main.c" } else { "main.cpp" });
+
+        let compiler = matches
+            .value_of("compiler")
+            .unwrap_or(if is_c { "gcc" } else { "g++" });
+
+        let freestanding = matches.is_present("freestanding");
 
-        let compiler = matches.value_of("compiler").ok_or("")?;
+        let standard = match matches.value_of("std") {
+            Some(standard) => Some(standard),
+            None if freestanding => None,
+            None => Some(if is_c { "c99" } else { "c++11" }),
+        };
 
-        let standard = matches.value_of("std").unwrap();
+        let c_standard = match matches.value_of("c_std") {
+            Some(standard) => Some(standard),
+            None if freestanding || !mixed => None,
+            None => Some("c99"),
+        };
 
         let opt_level = matches.value_of("opt").unwrap();
 
@@ -43,6 +216,301 @@ impl<'cli> Cli<'cli> {
 
         let examples: HashSet<_> = matches.values_of("examples").unwrap().collect();
 
+        let mut pkg_config: Vec<_> = matches
+            .values_of("pkg_config")
+            .map(|v| v.map(str::to_owned).collect())
+            .unwrap_or_default();
+
+        if let Some(packages_file) = matches.value_of("packages_file") {
+            pkg_config.extend(read_list_file(packages_file)?);
+        }
+
+        let explain_includes = matches.is_present("explain_includes");
+
+        let self_dep = matches.is_present("self_dep");
+
+        let test_framework = matches.value_of("test_framework");
+
+        let obj_suffix = matches.value_of("obj_suffix").unwrap();
+        let obj_suffix = if obj_suffix.starts_with('.') {
+            obj_suffix.to_owned()
+        } else {
+            format!(".{}", obj_suffix)
+        };
+
+        let test_keep_going = matches.is_present("test_keep_going");
+
+        let whole_archives: Vec<_> = matches
+            .values_of("whole_archive")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let hash_cache = matches.is_present("hash_cache");
+
+        let emit_dot = matches.is_present("emit_dot");
+
+        let dep_graph_target = matches.is_present("dep_graph_target");
+
+        let modules = matches.is_present("modules");
+        if modules && extension != "cpp" {
+            return Err("C++20 modules (--modules) are only supported for C++ files".into());
+        }
+
+        // The compiler's dependencies are the --auto-deps ones
+        let depmode = matches.value_of("depmode").unwrap();
+        let auto_deps = matches.is_present("auto_deps") || depmode == "compiler";
+        if matches.is_present("depfile_dir") && !auto_deps {
+            return Err("--depfile-dir requires --auto-deps or --depmode compiler".into());
+        }
+
+        let warn_missing = matches.is_present("warn_missing");
+
+        let strict_includes = matches.is_present("strict_includes");
+
+        let max_warnings = matches
+            .value_of("max_warnings")
+            .map(|count| {
+                count.parse::<usize>().map_err(|_| {
+                    format!(
+                        "Expected a number of warnings for --max-warnings but got {}",
+                        count
+                    )
+                })
+            })
+            .transpose()?;
+
+        let link_jobs = matches
+            .value_of("link_jobs")
+            .map(|count| {
+                count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| {
+                        format!(
+                            "Expected a positive number of link steps for --link-jobs but got {}",
+                            count
+                        )
+                    })
+            })
+            .transpose()?;
+
+        let warn_long_compile_units = matches
+            .value_of("warn_long_compile_units")
+            .map(|count| {
+                count.parse::<usize>().map_err(|_| {
+                    format!(
+                        "Expected a number of headers for --warn-long-compile-units but got {}",
+                        count
+                    )
+                })
+            })
+            .transpose()?;
+
+        let binary_lflags = matches
+            .values_of("binary_lflags")
+            .map(|v| v.map(split_assignment).collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        let generated = matches
+            .values_of("generated")
+            .map(|v| v.map(split_assignment).collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        let object_recipes = matches
+            .values_of("object_recipe")
+            .map(|v| v.map(split_assignment).collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        let programs = matches
+            .values_of("program")
+            .map(|v| v.map(split_assignment).collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        // Keep the quotes, they tell user includes apart from system ones
+        let include_macros = matches
+            .values_of("include_macro")
+            .map(|v| {
+                v.map(|arg| {
+                    arg.find('=')
+                        .map(|index| (&arg[..index], &arg[index + 1..]))
+                        .ok_or_else(|| format!("Expected NAME=VALUE but got {}", arg))
+                })
+                .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        // The system file is overridden by the project's own link map
+        let env_link_map = std::env::var_os(LINK_MAP_ENV).map(PathBuf::from);
+        let mut link_map =
+            load_system_link_map(env_link_map.as_deref(), Path::new(SYSTEM_LINK_MAP))?;
+        if let Some(path) = matches.value_of("link_map") {
+            link_map.extend(load_link_map(Path::new(path))?);
+        }
+
+        let lint_target = matches.is_present("lint_target");
+
+        let linter = matches.value_of("linter").unwrap();
+
+        let toolchain_prefix = matches.value_of("toolchain_prefix").unwrap_or("");
+
+        let objcopy: Vec<_> = matches
+            .values_of("objcopy")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let summary_json = matches.value_of("summary_json");
+
+        let normalize_paths = matches.value_of("normalize_paths") == Some("on");
+
+        let iquote: Vec<_> = matches
+            .values_of("iquote")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let fuzz = matches.value_of("fuzz");
+
+        let exclude_files: Vec<_> = matches
+            .values_of("exclude_file")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let relocatable = matches.is_present("relocatable");
+
+        let pie = matches.is_present("pie");
+
+        let depfile_dir = matches.value_of("depfile_dir");
+
+        let source_roots: Vec<_> = matches
+            .values_of("source_root")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let rpaths: Vec<_> = matches
+            .values_of("rpath")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let dist = matches.is_present("dist");
+
+        let archive_format = matches.value_of("archive_format").unwrap();
+
+        let compile_flags = matches.is_present("compile_flags");
+
+        let sort_by_deps = matches.is_present("sort_by_deps");
+
+        let touch_only = matches.is_present("touch_only");
+
+        let print_deps = matches.value_of("print_deps");
+
+        let format = matches.value_of("format").unwrap();
+
+        let skip_included_sources = matches.is_present("skip_included_sources");
+
+        let tags_target = matches.is_present("tags_target");
+
+        let ctags = matches.value_of("ctags").unwrap();
+
+        let link_groups: Vec<Vec<_>> = matches
+            .values_of("link_group")
+            .map(|groups| groups.map(|g| g.split_whitespace().collect()).collect())
+            .unwrap_or_default();
+
+        // The pattern rules need the objects to mirror the sources
+        let mirror_dirs = matches.is_present("mirror_dirs") || matches.is_present("compact");
+
+        let require_guards = matches.is_present("require_guards");
+
+        let build_profiles = matches.is_present("build_profiles");
+
+        let linker = matches.value_of("linker");
+
+        let version_from = matches.value_of("version_from");
+
+        // The library is named after the project unless named explicitly
+        let static_lib = matches.value_of("static_lib").or_else(|| {
+            matches
+                .value_of("lib")
+                .filter(|kind| *kind == "static")
+                .map(|_| binary)
+        });
+
+        let emit_bear_db = matches.is_present("emit_bear_db");
+
+        let flat = matches.is_present("flat");
+
+        let lazy_libm = matches.is_present("lazy_libm");
+
+        let detect_by_content = matches.is_present("detect_by_content");
+
+        let dump_partitions = matches.is_present("dump_partitions");
+
+        let lto = matches.is_present("lto");
+
+        let print_plan = matches.is_present("print_plan");
+
+        let compiler_dep = matches.is_present("compiler_dep");
+
+        let include_mks: Vec<_> = matches
+            .values_of("include_mk")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let echo_includes = matches.is_present("echo_includes");
+
+        let test_runner = matches.value_of("test_runner");
+
+        let explain = matches.value_of("explain");
+
+        let canonical_headers = matches.is_present("canonical_headers");
+
+        let include_dirs: Vec<_> = matches
+            .values_of("include_dir")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let cflags = matches.value_of("cflags");
+
+        let libs: Vec<_> = matches
+            .values_of("libs")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let ldflags = matches.value_of("ldflags");
+
+        let defines: Vec<_> = matches
+            .values_of("define")
+            .map(|v| v.collect())
+            .unwrap_or_default();
+
+        let debug_switch = matches.is_present("debug_switch");
+
+        let sanitizers = matches.is_present("sanitizers");
+
+        let format_targets = matches.is_present("format_targets");
+
+        let tidy_target = matches.is_present("tidy_target");
+
+        let install: Option<Vec<_>> = matches.is_present("install").then(|| {
+            matches
+                .values_of("install")
+                .map(|v| v.collect())
+                .unwrap_or_default()
+        });
+
+        let project_version = matches.value_of("project_version").unwrap_or("0.1.0");
+
+        let lib = matches.value_of("lib");
+
+        let valgrind = matches.is_present("valgrind");
+
+        let compact = matches.is_present("compact");
+
         Ok(Self {
             binary,
             main_file,
@@ -53,6 +521,1175 @@ impl<'cli> Cli<'cli> {
             tests,
             benchmarks,
             examples,
+            pkg_config,
+            explain_includes,
+            self_dep,
+            test_framework,
+            obj_suffix,
+            test_keep_going,
+            whole_archives,
+            hash_cache,
+            emit_dot,
+            dep_graph_target,
+            modules,
+            warn_missing,
+            strict_includes,
+            binary_lflags,
+            lint_target,
+            linter,
+            toolchain_prefix,
+            objcopy,
+            summary_json,
+            normalize_paths,
+            iquote,
+            fuzz,
+            exclude_files,
+            relocatable,
+            pie,
+            auto_deps,
+            depfile_dir,
+            generated,
+            source_roots,
+            rpaths,
+            programs,
+            dist,
+            archive_format,
+            include_macros,
+            compile_flags,
+            sort_by_deps,
+            touch_only,
+            link_map,
+            print_deps,
+            format,
+            skip_included_sources,
+            tags_target,
+            ctags,
+            link_groups,
+            mirror_dirs,
+            require_guards,
+            build_profiles,
+            warn_long_compile_units,
+            linker,
+            version_from,
+            static_lib,
+            emit_bear_db,
+            flat,
+            lazy_libm,
+            freestanding,
+            detect_by_content,
+            max_warnings,
+            dump_partitions,
+            lto,
+            print_plan,
+            compiler_dep,
+            object_recipes,
+            include_mks,
+            link_jobs,
+            echo_includes,
+            test_runner,
+            explain,
+            canonical_headers,
+            mixed,
+            include_dirs,
+            excludes,
+            cflags,
+            libs,
+            ldflags,
+            defines,
+            debug_switch,
+            sanitizers,
+            format_targets,
+            tidy_target,
+            install,
+            project_version,
+            lib,
+            valgrind,
+            depmode,
+            compact,
+            c_standard,
+            invocation: Vec::new(),
         })
     }
 }
+
+// Adds the options of the project's makegen.toml to the command line arguments.
+// The file uses the long option names as keys (e.g. binary = "foo", tests = ["tests"], lto = true)
+// and the options given on the command line take precedence over it
+pub fn matches_with_config(
+    matches: ArgMatches<'static>,
+    args: &[String],
+    root_dir: &Path,
+) -> Result<ArgMatches<'static>, Box<dyn Error>> {
+    let path = root_dir.join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(matches);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let table: toml::value::Table = toml::from_str(&contents)
+        .map_err(|e| format!("Could not parse {}: {}", path.display(), e))?;
+
+    let long_options = long_options();
+    let mut config_args = Vec::new();
+    for (key, value) in &table {
+        // The file is looked up in the root directory, which --git-root decides on
+        if key == "git-root" {
+            return Err(format!(
+                "git-root can't be set in {}, provide --git-root on the command line instead",
+                path.display()
+            )
+            .into());
+        }
+
+        let name = long_options
+            .get(key.as_str())
+            .ok_or_else(|| format!("Unknown option {} in {}", key, path.display()))?;
+        if matches.occurrences_of(name) > 0 {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => config_args.push(format!("--{}", key)),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(value) => config_args.push(format!("--{}={}", key, value)),
+                toml::Value::Integer(value) => config_args.push(format!("--{}={}", key, value)),
+                _ => {
+                    return Err(format!(
+                        "Expected a string, a number, a boolean or an array of them for {} in {}",
+                        key,
+                        path.display()
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+
+    // Report the mistakes of the file as its own, rather than the command line's
+    app()
+        .setting(AppSettings::ColorNever)
+        .get_matches_from_safe(
+            std::iter::once("makegen").chain(config_args.iter().map(String::as_str)),
+        )
+        .map_err(|e| {
+            let message = e.message.lines().next().unwrap_or_default();
+            format!(
+                "Invalid {}: {}",
+                path.display(),
+                message.trim_start_matches("error: ")
+            )
+        })?;
+
+    let program = args.iter().take(1);
+    let config_args = config_args.iter();
+    Ok(app().get_matches_from(program.chain(config_args).chain(args.iter().skip(1))))
+}
+
+// The names of the arguments by their long option names, which are the keys of makegen.toml.
+// clap 2 has no public API listing the arguments of an App, hence its parser is looked into
+fn long_options() -> HashMap<&'static str, &'static str> {
+    let app = app();
+    let flags = app.p.flags.iter().map(|flag| (flag.s.long, flag.b.name));
+    let opts = app.p.opts.iter().map(|opt| (opt.s.long, opt.b.name));
+
+    flags
+        .chain(opts)
+        .filter_map(|(long, name)| long.map(|long| (long, name)))
+        .collect()
+}
+
+// The project's root: the directory makegen runs in, or with --git-root the top-level directory of its git repository.
+// The root becomes the current directory, so that the Makefile is written to it and the paths given to makegen are relative to it
+pub fn enter_root_dir(matches: &ArgMatches) -> Result<PathBuf, Box<dyn Error>> {
+    let current_dir = std::env::current_dir()?;
+    if !matches.is_present("git_root") {
+        return Ok(current_dir);
+    }
+
+    let root_dir = git_root(&current_dir)?;
+    std::env::set_current_dir(&root_dir)?;
+    Ok(root_dir)
+}
+
+// The top-level directory of the git repository the directory is in.
+// Without git installed, it's the closest directory with a .git entry in it
+fn git_root(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let not_a_repository = || {
+        format!(
+            "{} is not inside a git repository, which --git-root needs",
+            dir.display()
+        )
+    };
+
+    let output = match Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+    {
+        Ok(output) => output,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return dir
+                .ancestors()
+                .find(|ancestor| ancestor.join(".git").exists())
+                .map(Path::to_path_buf)
+                .ok_or_else(|| not_a_repository().into());
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    if !output.status.success() {
+        return Err(not_a_repository().into());
+    }
+
+    Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim_end()))
+}
+
+// Splits a NAME=VALUE argument
+fn split_assignment(arg: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    let index = arg
+        .find('=')
+        .ok_or_else(|| format!("Expected NAME=VALUE but got {}", arg))?;
+
+    Ok((&arg[..index], arg[index + 1..].trim_matches('"')))
+}
+
+// The system link map is optional, unless its location is given explicitly (by the environment variable)
+fn load_system_link_map(
+    env_path: Option<&Path>,
+    system_path: &Path,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    match env_path {
+        Some(path) => load_link_map(path),
+        None if system_path.exists() => load_link_map(system_path),
+        None => Ok(HashMap::new()),
+    }
+}
+
+// Reads a TOML file mapping headers to libraries, e.g. foo.h = "foo".
+// Header names don't need quoting, the tables their dots create are flattened back
+fn load_link_map(path: &Path) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    fn flatten(
+        prefix: &str,
+        table: &toml::value::Table,
+        link_map: &mut HashMap<String, String>,
+    ) -> Result<(), String> {
+        for (key, value) in table {
+            let header = if prefix.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+
+            match value {
+                toml::Value::String(library) => {
+                    link_map.insert(header, library.to_owned());
+                }
+                toml::Value::Table(table) => flatten(&header, table, link_map)?,
+                _ => return Err(format!("Expected a library name for {}", header)),
+            }
+        }
+
+        Ok(())
+    }
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let table: toml::value::Table = toml::from_str(&contents)
+        .map_err(|e| format!("Could not parse {}: {}", path.display(), e))?;
+
+    let mut link_map = HashMap::new();
+    flatten("", &table, &mut link_map)
+        .map_err(|e| format!("Invalid link map {}: {}", path.display(), e))?;
+
+    Ok(link_map)
+}
+
+// Reads a file with one entry per line, skipping empty lines and # comments
+fn read_list_file(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("makegen")
+        .version("2.6")
+        .author("George Liontos <georgeliontos98@gmail.com>")
+        .about("Generate C/C++ makefiles quickly and easily!")
+        .arg(
+            Arg::with_name("compiler")
+                .short("c")
+                .long("compiler")
+                .value_name("COMPILER")
+                .help("Choose what compiler to use when compiling (defaults to gcc for C and g++ for C++)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("extension")
+                .short("e")
+                .long("extension")
+                .value_name("EXTENSION")
+                .help("Choose what extensions should the generator look for. It must be c for C files and cpp for C++ files. If omitted, it is detected from the source files")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("bin")
+                .short("b")
+                .long("binary")
+                .value_name("PROGRAM_NAME")
+                .help("Choose what the program of the generated executable should be")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("std")
+                .long("std")
+                .value_name("C/C++ Standard")
+                .help("Specifies the standard to use when compiling (defaults to c99 for C and c++11 for C++)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("opt")
+                .long("opt")
+                .value_name("OPTIMIZATION_LEVEL")
+                .help("Specifies the optimization level to include in the compiler flags")
+                .takes_value(true)
+                .default_value("O0")
+                .min_values(1)
+                .max_values(1),
+        )
+        .arg(
+            Arg::with_name("tests")
+                .long("tests")
+                .value_name("(TEST_FILE|TESTS_DIRECTORY)*")
+                .help("Specifies the directory or files that are tests files and have a main function")
+                .takes_value(true)
+                .default_value("tests")
+                .multiple(true)
+                .min_values(1),
+        )
+        .arg(
+            Arg::with_name("benchmarks")
+                .long("benchmarks")
+                .value_name("(BENCHMARK_FILE|BENCHMARKS_DIRECTORY)*")
+                .help("Specifies the directory or files that are benchmark files and have a main function")
+                .takes_value(true)
+                .default_value("benchmarks")
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("examples")
+                .long("examples")
+                .value_name("(EXAMPLE_FILE|EXAMPLES_DIRECTORY)*")
+                .help("Specifies the directory or files that are example files and have a main function")
+                .takes_value(true)
+                .default_value("examples")
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("main_file")
+                .long("main-file")
+                .value_name("MAIN_SOURCE_FILE")
+                .help("Specify the name of the main source file (the one that will be associated with the program name given with --binary). Defaults to main.c or main.cpp")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("pkg_config")
+                .long("pkg-config")
+                .value_name("PACKAGE*")
+                .help("Specifies libraries whose compiler and linker flags are queried through pkg-config")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("packages_file")
+                .long("packages-file")
+                .value_name("FILE")
+                .help("Specifies a file listing one pkg-config package per line (handled like --pkg-config)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("explain_includes")
+                .long("explain-includes")
+                .help("Prints every #include of the parsed files with its classification (System/User) and the path it resolves to, then exits")
+        )
+        .arg(
+            Arg::with_name("self_dep")
+                .long("self-dep")
+                .help("Makes every object and binary rule depend on the Makefile itself, so editing it triggers a rebuild")
+        )
+        .arg(
+            Arg::with_name("test_framework")
+                .long("test-framework")
+                .value_name("FRAMEWORK")
+                .help("Adds the compiler and linker flags of a known test framework to the tests target")
+                .takes_value(true)
+                .possible_values(&["unity", "criterion", "catch2", "gtest", "cmocka"])
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("obj_suffix")
+                .long("obj-suffix")
+                .value_name("SUFFIX")
+                .help("Specifies the extension of the generated object files")
+                .takes_value(true)
+                .default_value(".o")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("test_keep_going")
+                .long("test-keep-going")
+                .help("Makes the check target run every test even if some fail and exit with an error at the end")
+        )
+        .arg(
+            Arg::with_name("whole_archive")
+                .long("link-whole-archive")
+                .value_name("STATIC_LIBRARY*")
+                .help("Links the given static libraries with --whole-archive so that none of their objects are dropped")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("hash_cache")
+                .long("hash-cache")
+                .help("Caches the parsed files keyed on a hash of their contents, so unchanged files are not parsed again")
+        )
+        .arg(
+            Arg::with_name("emit_dot")
+                .long("emit-dot")
+                .help("Writes the include graph to deps.dot in Graphviz's DOT format instead of generating a Makefile")
+        )
+        .arg(
+            Arg::with_name("dep_graph_target")
+                .long("dep-graph-target")
+                .help("Generates a dep-graph target that regenerates deps.dot and renders it to deps.png with Graphviz")
+        )
+        .arg(
+            Arg::with_name("modules")
+                .long("modules")
+                .help("Enables basic C++20 modules support: module interface units (.cppm/.ixx) are compiled before the files importing them")
+        )
+        .arg(
+            Arg::with_name("warn_missing")
+                .long("warn-missing")
+                .help("Prints a warning for every user include that couldn't be found (they are skipped silently otherwise)")
+        )
+        .arg(
+            Arg::with_name("strict_includes")
+                .long("strict-includes")
+                .help("Fails listing all of the user includes that couldn't be found instead of skipping them")
+        )
+        .arg(
+            Arg::with_name("binary_lflags")
+                .long("binary-lflags")
+                .value_name("BINARY=FLAGS")
+                .help("Adds extra link flags to the link rule of the given binary only")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+        )
+        .arg(
+            Arg::with_name("lint_target")
+                .long("lint-target")
+                .help("Generates a lint target that runs the linter over all of the source files")
+        )
+        .arg(
+            Arg::with_name("linter")
+                .long("linter")
+                .value_name("COMMAND")
+                .help("Specifies the linter command the lint target runs")
+                .takes_value(true)
+                .default_value("cppcheck")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("toolchain_prefix")
+                .long("toolchain-prefix")
+                .value_name("PREFIX")
+                .help("Specifies a prefix for the toolchain's programs (e.g. arm-none-eabi-)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("objcopy")
+                .long("objcopy")
+                .value_name("FORMAT*")
+                .help("Generates targets converting the main binary with objcopy to the given formats (e.g. binary, ihex)")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("summary_json")
+                .long("summary-json")
+                .value_name("FILE")
+                .help("Writes a JSON report of the generated targets and the warnings to the given file")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("normalize_paths")
+                .long("normalize-paths")
+                .value_name("on|off")
+                .help("Whether to rewrite the emitted paths to use forward slashes")
+                .takes_value(true)
+                .possible_values(&["on", "off"])
+                .default_value("on")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("iquote")
+                .long("iquote")
+                .value_name("DIR*")
+                .help("Adds directories in which quoted includes are looked up (passed to the compiler with -iquote)")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("fuzz")
+                .long("fuzz")
+                .value_name("FILE")
+                .help("Generates a fuzz target which builds the given libFuzzer harness with the fuzzer and address sanitizers")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("exclude_file")
+                .long("exclude-file")
+                .value_name("PATH*")
+                .help("Excludes the given files (relative to the project's root) from the generated Makefile")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("relocatable")
+                .long("relocatable")
+                .help("Generates a Makefile which works regardless of the directory make is invoked from (e.g. with make -f)")
+        )
+        .arg(
+            Arg::with_name("pie")
+                .long("pie")
+                .help("Builds the binaries as position-independent executables")
+        )
+        .arg(
+            Arg::with_name("auto_deps")
+                .long("auto-deps")
+                .help("Makes the compiler also write the dependencies of each object file (-MMD -MP) and includes them in the Makefile")
+        )
+        .arg(
+            Arg::with_name("depfile_dir")
+                .long("depfile-dir")
+                .value_name("DIR")
+                .help("Specifies the directory the dependency files of --auto-deps are written to instead of the objects' directory")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("generated")
+                .long("generated")
+                .value_name("FILE=COMMAND")
+                .help("Specifies a file generated by the given command before the files depending on it are compiled (can appear multiple times)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+        )
+        .arg(
+            Arg::with_name("source_root")
+                .long("source-root")
+                .value_name("DIR*")
+                .help("Specifies the directories (inside the project) to look for source files in, instead of the whole project")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("rpath")
+                .long("rpath")
+                .value_name("DIR")
+                .help("Adds a directory to the runtime library search path of the binaries (can appear multiple times)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("program")
+                .long("program")
+                .value_name("NAME=FILE")
+                .help("Builds the file containing a main function as the program with the given name (can appear multiple times)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("dist")
+                .long("dist")
+                .help("Generates a dist target which packages the sources and headers into <PROGRAM_NAME>-$(VERSION).<FORMAT>")
+        )
+        .arg(
+            Arg::with_name("archive_format")
+                .long("archive-format")
+                .value_name("FORMAT")
+                .help("Specifies the format of the archive created by the dist target")
+                .takes_value(true)
+                .possible_values(&["tar.gz", "tar.bz2", "tar.xz", "zip"])
+                .default_value("tar.gz")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("include_macro")
+                .long("include-macro")
+                .value_name("NAME=PATH")
+                .help("Specifies what a macro used in #include expands to, e.g. CONFIG_H=\"config.h\" (can appear multiple times)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("compile_flags")
+                .long("compile-flags")
+                .help("Also writes the compiler flags to compile_flags.txt for clangd")
+        )
+        .arg(
+            Arg::with_name("sort_by_deps")
+                .long("sort-by-deps")
+                .help("Orders the object rules by their number of dependencies instead of by name")
+        )
+        .arg(
+            Arg::with_name("touch_only")
+                .long("touch-only")
+                .help("Only updates the dependency variables of the existing Makefile, leaving everything else untouched")
+        )
+        .arg(
+            Arg::with_name("link_map")
+                .long("link-map")
+                .value_name("FILE")
+                .help("Specifies a TOML file mapping system headers to the libraries to link against (e.g. foo.h = \"foo\")")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("print_deps")
+                .long("print-deps")
+                .value_name("FILE")
+                .help("Prints all of the files the given file depends on, directly or not, and exits")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Specifies what to generate: a Makefile, a Makefile.am for Automake or a meson.build for Meson")
+                .takes_value(true)
+                .possible_values(&["make", "automake", "meson"])
+                .default_value("make")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("skip_included_sources")
+                .long("skip-included-sources")
+                .help("Doesn't compile source files on their own when another file includes them directly")
+        )
+        .arg(
+            Arg::with_name("tags_target")
+                .long("tags-target")
+                .help("Generates a tags target that runs ctags over the source directories")
+        )
+        .arg(
+            Arg::with_name("ctags")
+                .long("ctags")
+                .value_name("COMMAND")
+                .help("Specifies the ctags command the tags target runs")
+                .takes_value(true)
+                .default_value("ctags")
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("link_group")
+                .long("link-group")
+                .value_name("STATIC_LIBRARIES")
+                .help("Links the given static libraries (e.g. \"a b c\") inside --start-group/--end-group so they can depend on each other (can appear multiple times)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("mirror_dirs")
+                .long("mirror-dirs")
+                .help("Places the object files (and depfiles) in the same directory structure as the sources under the object directory")
+        )
+        .arg(
+            Arg::with_name("require_guards")
+                .long("require-guards")
+                .help("Fails listing the headers that have neither an include guard nor #pragma once")
+        )
+        .arg(
+            Arg::with_name("build_profiles")
+                .long("build-profiles")
+                .help("Selects debug or release flags with make BUILD=debug|release, keeping the objects of each apart")
+        )
+        .arg(
+            Arg::with_name("warn_long_compile_units")
+                .long("warn-long-compile-units")
+                .value_name("HEADERS")
+                .help("Prints a warning for every translation unit that includes more than the given number of headers, directly or not")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("linker")
+                .long("linker")
+                .value_name("COMMAND")
+                .help("Specifies the command that links the binaries, instead of the compiler")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("version_from")
+                .long("version-from")
+                .value_name("git|FILE")
+                .help("Defines the VERSION macro as a string, taken from git describe or the given file")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("static_lib")
+                .long("static-lib")
+                .value_name("NAME")
+                .help("Also archives the objects of every file that isn't a program, test, benchmark or example into libNAME.a")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("emit_bear_db")
+                .long("emit-bear-db")
+                .help("Generates a compdb target that captures the compile commands that actually run into compile_commands.json with bear")
+        )
+        .arg(
+            Arg::with_name("flat")
+                .long("flat")
+                .help("Names the object files and variables after the base names of the files only (they must be unique)")
+                .conflicts_with("mirror_dirs")
+        )
+        .arg(
+            Arg::with_name("lazy_libm")
+                .long("lazy-libm")
+                .help("Only links against the math library (-lm) if a math function appears to be called, not whenever <math.h> is included")
+        )
+        .arg(
+            Arg::with_name("freestanding")
+                .long("freestanding")
+                .help("Builds without the standard library (-ffreestanding -nostdlib) and without a default standard")
+        )
+        .arg(
+            Arg::with_name("detect_by_content")
+                .long("detect-by-content")
+                .help("Also treats files without an extension as sources if their content looks like C/C++ (it includes other files and doesn't start with a shebang)")
+        )
+        .arg(
+            Arg::with_name("max_warnings")
+                .long("max-warnings")
+                .value_name("N")
+                .help("Prints at most N warnings, followed by how many more there are")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("dump_partitions")
+                .long("dump-partitions")
+                .help("Prints the files of each partition (standalone, tests, benchmarks and examples) and exits")
+        )
+        .arg(
+            Arg::with_name("lto")
+                .long("lto")
+                .help("Builds with link-time optimization (-flto), both when compiling and when linking")
+        )
+        .arg(
+            Arg::with_name("print_plan")
+                .long("print-plan")
+                .help("Prints a summary of the programs, tests and libraries the Makefile builds before generating it")
+        )
+        .arg(
+            Arg::with_name("compiler_dep")
+                .long("compiler-dep")
+                .help("Rebuilds the object files when the compiler changes, either updated or switched to another one")
+        )
+        .arg(
+            Arg::with_name("object_recipe")
+                .long("object-recipe")
+                .value_name("FILE=RECIPE")
+                .help("Compiles the given source file with the recipe instead of the default one, keeping its prerequisites")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+        )
+        .arg(
+            Arg::with_name("include_mk")
+                .long("include-mk")
+                .value_name("PATTERN")
+                .help("Includes the makefile fragments matching the pattern (e.g. rules/*.mk) at the top of the Makefile")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("link_jobs")
+                .long("link-jobs")
+                .value_name("N")
+                .help("Runs at most N link steps at the same time, while the object files are still compiled in parallel")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("git_root")
+                .long("git-root")
+                .help("Uses the top-level directory of the git repository as the project's root instead of the current directory")
+        )
+        .arg(
+            Arg::with_name("echo_includes")
+                .long("echo-includes")
+                .help("Prints the dependencies of each object file to stderr before compiling it, to debug includes at build time")
+        )
+        .arg(
+            Arg::with_name("test_runner")
+                .long("test-runner")
+                .value_name("FILE")
+                .help("Links the test files without a main function into the given test, which runs all of them")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("explain")
+                .long("explain")
+                .value_name("FILE")
+                .help("Explains why the given file is (or isn't) part of the build, which partition it belongs to and what it depends on, and exits")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+        )
+        .arg(
+            Arg::with_name("canonical_headers")
+                .long("canonical-headers")
+                .help("Collapses the different spellings of each file in the dependencies into its canonical path")
+        )
+        .arg(
+            Arg::with_name("mixed")
+                .long("mixed")
+                .help("Builds the C sources of a C++ project as well, compiling them with $(CC) and linking everything with $(CXX)")
+        )
+        .arg(
+            Arg::with_name("include_dir")
+                .long("include-dir")
+                .value_name("DIR")
+                .help("Adds a directory in which both quoted and angle bracket includes are looked up (passed to the compiler with -I)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("PATH*")
+                .help("Skips the given files and directories (relative to the project's root) when looking for source files")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("cflags")
+                .long("cflags")
+                .value_name("FLAGS")
+                .help("Appends extra flags to the CFLAGS, e.g. --cflags \"-Wextra -fno-exceptions\"")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .allow_hyphen_values(true)
+        )
+        .arg(
+            Arg::with_name("libs")
+                .long("libs")
+                .value_name("LIBRARY*")
+                .help("Links the binaries against the given libraries, along with the ones detected from the includes")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+        )
+        .arg(
+            Arg::with_name("ldflags")
+                .long("ldflags")
+                .value_name("FLAGS")
+                .help("Appends extra flags to the LFLAGS, e.g. --ldflags \"-L/opt/lib\"")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .allow_hyphen_values(true)
+        )
+        .arg(
+            Arg::with_name("define")
+                .long("define")
+                .value_name("NAME[=VALUE]")
+                .help("Defines a macro for the compiler (with -D) and for the conditional includes while parsing")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("debug_switch")
+                .long("debug-switch")
+                .help("Builds with -O0 -g instead of the optimization level with make DEBUG=1, keeping the objects of each mode apart")
+                .conflicts_with("build_profiles")
+        )
+        .arg(
+            Arg::with_name("sanitizers")
+                .long("sanitizers")
+                .help("Generates asan, ubsan and tsan targets that build the project with the respective sanitizer")
+        )
+        .arg(
+            Arg::with_name("format_targets")
+                .long("format-targets")
+                .help("Generates a format target that runs clang-format over the sources and headers and a format-check one that fails if any is unformatted")
+        )
+        .arg(
+            Arg::with_name("tidy_target")
+                .long("tidy-target")
+                .help("Generates a tidy target that runs clang-tidy over all of the source files with the flags they are compiled with")
+        )
+        .arg(
+            Arg::with_name("install")
+                .long("install")
+                .value_name("ARTIFACT*")
+                .help("Generates install and uninstall targets for the given artifacts (the binaries, and the library and its headers with --static-lib, by default)")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(0)
+                .possible_values(&["binaries", "library", "headers"])
+        )
+        .arg(
+            Arg::with_name("project_version")
+                .long("project-version")
+                .value_name("VERSION")
+                .help("Specifies the version the dist archive is named after (0.1.0 by default), unless VERSION is given to make")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .requires("dist")
+                .conflicts_with("version_from")
+        )
+        .arg(
+            Arg::with_name("lib")
+                .long("lib")
+                .value_name("KIND")
+                .help("Builds the library code into lib<PROGRAM_NAME>.a (or the --static-lib name), linking the programs and tests against it, or lib<PROGRAM_NAME>.so")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .possible_values(&["static", "shared"])
+        )
+        .arg(
+            Arg::with_name("valgrind")
+                .long("valgrind")
+                .help("Generates a memcheck target that runs the main program and the tests under valgrind")
+        )
+        .arg(
+            Arg::with_name("depmode")
+                .long("depmode")
+                .value_name("MODE")
+                .help("Chooses whether the objects depend on the headers makegen finds or only on the ones the compiler writes (-MMD -MP)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .possible_values(&["makegen", "compiler"])
+                .default_value("makegen")
+        )
+        .arg(
+            Arg::with_name("compact")
+                .long("compact")
+                .help("Compiles the sources with a pattern rule per extension instead of a rule per file (implies --mirror-dirs)")
+                .conflicts_with("flat")
+        )
+        .arg(
+            Arg::with_name("c_std")
+                .long("c-std")
+                .value_name("C Standard")
+                .help("Specifies the standard of the C sources of a mixed project (defaults to c99), --std being the C++ one")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(1)
+                .requires("mixed")
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_matches(config: &str, args: &[&str]) -> Result<ArgMatches<'static>, Box<dyn Error>> {
+        let root_dir = tempfile::tempdir().unwrap();
+        fs::write(root_dir.path().join(CONFIG_FILE), config).unwrap();
+
+        let args: Vec<String> = std::iter::once("makegen")
+            .chain(args.iter().copied())
+            .map(str::to_owned)
+            .collect();
+        matches_with_config(app().get_matches_from(&args), &args, root_dir.path())
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let matches = config_matches(
+            "binary = \"foo\"\nopt = \"O2\"\nlto = true\ntests = [\"tests\", \"more_tests\"]\n",
+            &["-b", "bar", "--tests", "unit"],
+        )
+        .unwrap();
+
+        assert_eq!(matches.value_of("bin"), Some("bar"));
+        assert_eq!(matches.value_of("opt"), Some("O2"));
+        assert!(matches.is_present("lto"));
+        assert_eq!(
+            matches.values_of("tests").unwrap().collect::<Vec<_>>(),
+            vec!["unit"]
+        );
+    }
+
+    #[test]
+    fn config_keys_map_to_argument_names() {
+        let matches = config_matches(
+            "binary = \"foo\"\nlink-whole-archive = [\"a\", \"b\"]\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(matches.value_of("bin"), Some("foo"));
+        assert_eq!(
+            matches
+                .values_of("whole_archive")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        let matches = config_matches(
+            "binary = \"foo\"\nlink-whole-archive = [\"a\", \"b\"]\n",
+            &["--link-whole-archive", "c"],
+        )
+        .unwrap();
+        assert_eq!(
+            matches
+                .values_of("whole_archive")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["c"]
+        );
+    }
+
+    #[test]
+    fn unknown_config_keys_are_rejected() {
+        let error = config_matches("binary = \"foo\"\nbinray = \"bar\"\n", &[]).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown option binray in "));
+
+        // Even the ones that would add nothing to the command line
+        let error = config_matches("binary = \"foo\"\nnonsense = false\n", &[]).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown option nonsense in "));
+
+        let error = config_matches("binary = \"foo\"\ngit-root = true\n", &[]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("provide --git-root on the command line"));
+    }
+
+    #[test]
+    fn system_link_map_is_read_from_the_env_path_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let env_path = dir.path().join("env.toml");
+        let system_path = dir.path().join("system.toml");
+        fs::write(&env_path, "foo.h = \"foo\"\n").unwrap();
+        fs::write(&system_path, "\"math.h\" = \"openlibm\"\n").unwrap();
+
+        let link_map = load_system_link_map(Some(&env_path), &system_path).unwrap();
+        assert_eq!(link_map.len(), 1);
+        assert_eq!(link_map["foo.h"], "foo");
+
+        let link_map = load_system_link_map(None, &system_path).unwrap();
+        assert_eq!(link_map.len(), 1);
+        assert_eq!(link_map["math.h"], "openlibm");
+
+        // Only the system file is optional
+        let missing = dir.path().join("missing.toml");
+        assert!(load_system_link_map(None, &missing).unwrap().is_empty());
+        assert!(load_system_link_map(Some(&missing), &system_path).is_err());
+    }
+
+    #[test]
+    fn git_root_is_the_repository_top_level() {
+        let root_dir = tempfile::tempdir().unwrap();
+        // The least git accepts as a repository
+        for dir in &[".git/objects", ".git/refs", "tools"] {
+            fs::create_dir_all(root_dir.path().join(dir)).unwrap();
+        }
+        fs::write(
+            root_dir.path().join(".git/HEAD"),
+            "ref: refs/heads/master\n",
+        )
+        .unwrap();
+
+        let top_level = git_root(&root_dir.path().join("tools")).unwrap();
+        assert_eq!(
+            top_level.canonicalize().unwrap(),
+            root_dir.path().canonicalize().unwrap()
+        );
+
+        let outside = tempfile::tempdir().unwrap();
+        assert!(git_root(outside.path()).is_err());
+    }
+}
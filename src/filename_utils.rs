@@ -10,14 +10,48 @@ pub fn strip_extension(source: &str) -> &str {
     }
 }
 
+// The extensions C++ sources are commonly written with, all of which the cpp extension stands for
+pub const CPP_EXTENSIONS: [&str; 4] = ["cpp", "cc", "cxx", "C"];
+
+// Stands for the sources of both languages in mixed C and C++ projects
+pub const MIXED_EXTENSION: &str = "c+cpp";
+
+// The extensions of the headers a source file may be paired with, in the order they are looked up
+pub const HEADER_EXTENSIONS: [&str; 4] = ["h", "hpp", "hh", "hxx"];
+
+// The extensions of the source files of the given extension
+#[inline]
+pub fn source_extensions(ext: &str) -> Vec<&str> {
+    match ext {
+        "cpp" => CPP_EXTENSIONS.to_vec(),
+        MIXED_EXTENSION => CPP_EXTENSIONS.iter().copied().chain(Some("c")).collect(),
+        ext => vec![ext],
+    }
+}
+
 #[inline]
 pub fn has_extension<P: AsRef<Path>>(path: P, ext: &str) -> bool {
     path.as_ref()
         .extension()
-        .map(|e| e.to_str().unwrap_or("") == ext)
+        .and_then(|e| e.to_str())
+        .map(|e| match ext {
+            "cpp" => CPP_EXTENSIONS.contains(&e),
+            MIXED_EXTENSION => e == "c" || CPP_EXTENSIONS.contains(&e),
+            ext => e == ext,
+        })
         .unwrap_or(false)
 }
 
+// Extensions of C++20 module interface units
+pub const MODULE_INTERFACE_EXTENSIONS: [&str; 2] = ["cppm", "ixx"];
+
+#[inline]
+pub fn is_module_interface<P: AsRef<Path>>(path: P) -> bool {
+    MODULE_INTERFACE_EXTENSIONS
+        .iter()
+        .any(|ext| has_extension(&path, ext))
+}
+
 #[inline]
 pub fn is_hidden(entry: &DirEntry) -> bool {
     entry
@@ -1,9 +1,15 @@
 use crate::{
     cli::Cli,
     filename_utils::*,
-    parser::{DependencyMap, ParseResult},
+    parser::{included_sources, DependencyMap, ParseResult, Parser},
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    io::prelude::*,
+    path::Path,
 };
-use std::{collections::HashSet, fs::File, io::prelude::*};
 
 struct GenerateContext<'c, 'p, 'd> {
     cli: &'c Cli<'c>,
@@ -29,6 +35,8 @@ impl<'c, 'p, 'd> GenerateContext<'c, 'p, 'd> {
 }
 
 struct PartitionedFiles<'f> {
+    // The standalone file (without its extension) that becomes the main program
+    main_file: String,
     standalone: Vec<&'f str>,
     tests: Vec<&'f str>,
     benchmarks: Vec<&'f str>,
@@ -37,15 +45,6 @@ struct PartitionedFiles<'f> {
 
 impl<'f> PartitionedFiles<'f> {
     pub fn partition(cli: &Cli, map: &'f DependencyMap) -> Self {
-        macro_rules! contained_in_partition {
-            ($cli:ident, $partition:ident, $running:ident) => {
-                $cli.$partition.iter().any(|f| {
-                    let f = self::strip_extension(f);
-                    $running.starts_with(f) || **$running == f
-                })
-            };
-        }
-
         let with_main: Vec<_> = map
             .keys()
             .filter(|k| map.get(*k).unwrap().1) // filter those which contain a main function
@@ -54,20 +53,20 @@ impl<'f> PartitionedFiles<'f> {
 
         let tests: Vec<_> = with_main
             .iter()
-            .filter(|v| contained_in_partition!(cli, tests, v))
-            .map(|v| *v)
+            .filter(|v| contained_in_partition(&cli.tests, v))
+            .copied()
             .collect();
 
         let benchmarks: Vec<_> = with_main
             .iter()
-            .filter(|v| contained_in_partition!(cli, benchmarks, v))
-            .map(|v| *v)
+            .filter(|v| contained_in_partition(&cli.benchmarks, v))
+            .copied()
             .collect();
 
         let examples: Vec<_> = with_main
             .iter()
-            .filter(|v| contained_in_partition!(cli, examples, v))
-            .map(|v| *v)
+            .filter(|v| contained_in_partition(&cli.examples, v))
+            .copied()
             .collect();
 
         let standalone: Vec<_> = with_main
@@ -75,7 +74,14 @@ impl<'f> PartitionedFiles<'f> {
             .filter(|v| !tests.contains(v) && !benchmarks.contains(v) && !examples.contains(v))
             .collect();
 
+        let main_file = if cli.normalize_paths {
+            find_main_file(&normalize_path(cli.main_file), &standalone)
+        } else {
+            find_main_file(cli.main_file, &standalone)
+        };
+
         Self {
+            main_file,
             standalone,
             tests,
             benchmarks,
@@ -84,6 +90,33 @@ impl<'f> PartitionedFiles<'f> {
     }
 }
 
+// A path-qualified main file (e.g. src/main.c) must match exactly. A bare file name
+// also matches a file in a subdirectory as long as there's only one with that name
+fn find_main_file(main_file: &str, standalone: &[&str]) -> String {
+    let main_file = strip_extension(main_file.trim_start_matches("./"));
+
+    if main_file.contains('/') || standalone.contains(&main_file) {
+        return main_file.to_owned();
+    }
+
+    let mut candidates = standalone
+        .iter()
+        .filter(|f| f.rsplit('/').next() == Some(main_file));
+
+    match (candidates.next(), candidates.next()) {
+        (Some(candidate), None) => (*candidate).to_owned(),
+        _ => main_file.to_owned(),
+    }
+}
+
+// Whether the file (without its extension) is one of the partition's files or lives under one of its directories
+fn contained_in_partition(partition: &HashSet<&str>, file: &str) -> bool {
+    partition.iter().any(|f| {
+        let f = strip_extension(f);
+        file.starts_with(f) || file == f
+    })
+}
+
 fn get_all_file_dependencies(file: &str, ext: &str, dep_map: &DependencyMap) -> Vec<String> {
     let mut seen = HashSet::new();
     let mut file_deps = Vec::new();
@@ -110,9 +143,13 @@ fn get_all_file_dependencies_r(
 
             let stripped = strip_extension(dependency);
             let complementary_file = if has_extension(dependency, ext) {
-                format!("{}.h", stripped)
+                HEADER_EXTENSIONS
+                    .iter()
+                    .map(|header_ext| format!("{}.{}", stripped, header_ext))
+                    .find(|header| dep_map.contains_key(header))
+                    .unwrap_or_default()
             } else {
-                format!("{}.{}", stripped, ext)
+                binary_source(dep_map, stripped, ext)
             };
 
             if dep_map.contains_key(&complementary_file) && !seen.contains(&complementary_file) {
@@ -123,11 +160,47 @@ fn get_all_file_dependencies_r(
     }
 }
 
-fn flatten_dependencies(dep_map: &DependencyMap, ext: &str) -> DependencyMap {
+// The dependency map with its paths in the form they're emitted in
+fn emitted_dependency_map<'r>(cli: &Cli, parse_result: &'r ParseResult) -> Cow<'r, DependencyMap> {
+    if !cli.normalize_paths {
+        return Cow::Borrowed(&parse_result.dependency_map);
+    }
+
+    Cow::Owned(
+        parse_result
+            .dependency_map
+            .iter()
+            .map(|(file, (dependencies, has_main))| {
+                let dependencies = dependencies.iter().map(|d| normalize_path(d)).collect();
+                (normalize_path(file), (dependencies, *has_main))
+            })
+            .collect(),
+    )
+}
+
+// GNU make accepts forward slashes on every platform
+fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+fn flatten_dependencies(dep_map: &DependencyMap, cli: &Cli) -> DependencyMap {
     let mut new_dep_map = DependencyMap::new();
 
-    for file in dep_map.keys().filter(|f| has_extension(f, ext)) {
-        let file_deps = get_all_file_dependencies(file, ext, &dep_map);
+    // Sources included by another file are already compiled as part of it
+    let skipped: HashSet<String> = if cli.skip_included_sources {
+        included_sources(dep_map, cli.source_extension())
+            .into_iter()
+            .map(|(_, source)| source)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    for file in dep_map
+        .keys()
+        .filter(|f| is_translation_unit(cli, f) && !skipped.contains(*f))
+    {
+        let file_deps = get_all_file_dependencies(file, cli.source_extension(), dep_map);
         let has_main = dep_map.get(file).unwrap().1;
         new_dep_map.insert(file.to_owned(), (file_deps, has_main));
     }
@@ -135,246 +208,4957 @@ fn flatten_dependencies(dep_map: &DependencyMap, ext: &str) -> DependencyMap {
     new_dep_map
 }
 
-pub fn generate_makefile(cli: &Cli, parse_result: ParseResult) -> std::io::Result<()> {
-    let mut makefile = File::create("Makefile")?;
-    let dep_map = flatten_dependencies(&parse_result.dependency_map, cli.extension);
-    let partitioned = PartitionedFiles::partition(cli, &parse_result.dependency_map);
-    let ctx = GenerateContext::new(cli, &partitioned, &dep_map, &parse_result.dlls);
+pub fn generate_makefile(cli: &Cli, parse_result: &ParseResult) -> std::io::Result<()> {
+    let mut makefile = Vec::new();
+    write_makefile(&mut makefile, cli, parse_result)?;
 
-    generate_compiler_variables(&mut makefile, &ctx)?;
-    generate_file_variables(&mut makefile, &ctx)?;
-    generate_targets(&mut makefile, &ctx)?;
+    // Leave the Makefile untouched if nothing changed so that make doesn't consider it modified
+    if fs::read("Makefile").ok().as_ref() != Some(&makefile) {
+        fs::write("Makefile", makefile)?;
+    }
 
     Ok(())
 }
 
-fn generate_compiler_variables(makefile: &mut File, ctx: &GenerateContext) -> std::io::Result<()> {
-    writeln!(
-        makefile,
-        "CC := {compiler}\n\
-        CFLAGS := -Wall\n\
-        CFLAGS += -std={std}\n\
-        CFLAGS += -{opt}\n\
-        LFLAGS := {link_flags}",
-        compiler = ctx.cli.compiler,
-        std = ctx.cli.standard,
-        opt = ctx.cli.opt_level,
-        link_flags = ctx
+pub fn generate_automake(cli: &Cli, parse_result: &ParseResult) -> std::io::Result<()> {
+    let mut makefile_am = Vec::new();
+    write_automake(&mut makefile_am, cli, parse_result)?;
+    fs::write("Makefile.am", makefile_am)
+}
+
+// Writes a Makefile.am with the same programs the Makefile would have
+fn write_automake(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let dep_map = flatten_dependencies(&dependency_map, cli);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+
+    let flags_var = if cli.extension == "c" {
+        "AM_CFLAGS"
+    } else {
+        "AM_CXXFLAGS"
+    };
+    let mut flags_vars = vec![(flags_var, cli.standard)];
+    // The C sources of mixed projects are compiled with the C flags, which get the C standard
+    if cli.mixed {
+        flags_vars.insert(0, ("AM_CFLAGS", cli.c_standard));
+    }
+    for (flags_var, standard) in flags_vars {
+        writeln!(
+            out,
+            "{} = -Wall{} -{}{}",
+            flags_var,
+            standard
+                .map(|standard| format!(" -std={}", standard))
+                .unwrap_or_default(),
+            cli.opt_level,
+            if cli.freestanding {
+                " -ffreestanding"
+            } else {
+                ""
+            }
+        )?;
+    }
+    if cli.freestanding {
+        writeln!(out, "AM_LDFLAGS = -nostdlib")?;
+    }
+
+    if !parse_result.dlls.is_empty() {
+        let libraries: Vec<_> = parse_result
             .dlls
             .iter()
             .map(|dll| format!("-l{}", dll))
-            .collect::<Vec<_>>()
-            .join(" ")
-    )?;
+            .collect();
+        writeln!(out, "LDADD = {}", libraries.join(" "))?;
+    }
 
-    Ok(())
-}
+    // The programs along with the files they are built from
+    let standalone: Vec<_> = partitioned
+        .standalone
+        .iter()
+        .map(|f| (standalone_binary(cli, &partitioned, f).1, *f))
+        .collect();
+    fn partition<'f>(files: &[&'f str]) -> Vec<(&'f str, &'f str)> {
+        files.iter().map(|f| (*f, *f)).collect()
+    }
+    let others: Vec<_> = partition(&partitioned.benchmarks)
+        .into_iter()
+        .chain(partition(&partitioned.examples))
+        .collect();
 
-fn generate_file_variables(makefile: &mut File, ctx: &GenerateContext) -> std::io::Result<()> {
-    writeln!(makefile, "\nODIR := .OBJ\n")?;
+    for (primary, programs) in &[
+        ("bin_PROGRAMS", standalone),
+        ("check_PROGRAMS", partition(&partitioned.tests)),
+        ("noinst_PROGRAMS", others),
+    ] {
+        if programs.is_empty() {
+            continue;
+        }
+
+        let names: Vec<_> = programs.iter().map(|(name, _)| *name).collect();
+        writeln!(out, "\n{} = {}", primary, names.join(" "))?;
 
-    for file in ctx.dep_map.keys() {
-        generate_source_file_dependencies_variable_for_file(makefile, file, ctx)?;
+        for (name, file) in programs {
+            let sources = &dep_map[&binary_source(&dep_map, file, cli.source_extension())].0;
+            writeln!(
+                out,
+                "{}_SOURCES = {}",
+                automake_canonical_name(name),
+                sources.join(" ")
+            )?;
+        }
     }
 
-    writeln!(makefile)?;
+    if !partitioned.tests.is_empty() {
+        writeln!(out, "\nTESTS = $(check_PROGRAMS)")?;
+    }
 
     Ok(())
 }
 
-fn generate_object_file_dependencies_variable_for_file(
-    makefile: &mut File,
-    file: &str,
-    ctx: &GenerateContext,
-) -> std::io::Result<()> {
-    let var_name = strip_extension(file);
-    let var_name = object_file_dependencies_var_name(var_name);
-    write!(makefile, "{} := ", var_name)?;
+pub fn generate_meson(cli: &Cli, parse_result: &ParseResult) -> std::io::Result<()> {
+    let mut meson_build = Vec::new();
+    write_meson(&mut meson_build, cli, parse_result)?;
+    fs::write("meson.build", meson_build)
+}
+
+// Writes a meson.build with the same programs the Makefile would have
+fn write_meson(out: &mut dyn Write, cli: &Cli, parse_result: &ParseResult) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let dep_map = flatten_dependencies(&dependency_map, cli);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+
+    // Meson has no optimization level for -Ofast, which is -O3 with -ffast-math
+    let (optimization, fast_math) = match cli.opt_level {
+        "Ofast" => ("3", true),
+        level => (level.trim_start_matches('O'), false),
+    };
+    let languages = if cli.mixed {
+        vec!["c", "cpp"]
+    } else {
+        vec![cli.extension]
+    };
+
+    let mut options = vec![
+        "warning_level=1".to_owned(),
+        format!("optimization={}", optimization),
+    ];
+    if let Some(standard) = cli.standard {
+        options.push(format!("{}_std={}", cli.extension, standard));
+    }
+    // The C sources of mixed projects have a standard of their own
+    if let Some(standard) = cli.c_standard.filter(|_| cli.mixed) {
+        options.push(format!("c_std={}", standard));
+    }
+    if fast_math {
+        for language in &languages {
+            options.push(format!("{}_args=-ffast-math", language));
+        }
+    }
+    if cli.lto {
+        options.push("b_lto=true".to_owned());
+    }
+    writeln!(
+        out,
+        "project('{}', {}, default_options : [{}])",
+        cli.binary,
+        if cli.mixed {
+            "['c', 'cpp']".to_owned()
+        } else {
+            format!("'{}'", cli.extension)
+        },
+        options
+            .iter()
+            .map(|option| format!("'{}'", option))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )?;
+
+    if cli.freestanding {
+        writeln!(
+            out,
+            "add_project_arguments('-ffreestanding', language : '{ext}')\n\
+            add_project_link_arguments('-nostdlib', language : '{ext}')",
+            ext = cli.extension
+        )?;
+    }
 
-    let dependencies = &ctx.dep_map.get(file).unwrap().0;
-    let object_dependencies = dependencies
+    // Meson finds the threads library itself, the rest are looked up by the compiler
+    let dependencies: Vec<_> = parse_result
+        .dlls
         .iter()
-        .filter(|d| has_extension(d, ctx.cli.extension))
-        .map(|d| format!("$(ODIR)/{}.o", escape_folder(strip_extension(d))))
-        .collect::<Vec<_>>()
-        .join(" ");
+        .map(|dll| match dll.as_str() {
+            "pthread" => "dependency('threads')".to_owned(),
+            dll => format!("cc.find_library('{}')", dll),
+        })
+        .collect();
+    writeln!(out)?;
+    if dependencies.iter().any(|d| d.starts_with("cc.")) {
+        writeln!(out, "cc = meson.get_compiler('{}')", cli.extension)?;
+    }
+    writeln!(out, "deps = [{}]\n", dependencies.join(", "))?;
 
-    writeln!(makefile, "{}", object_dependencies)?;
+    // Target names can't contain path separators, unlike the programs built from them
+    let mut executable = |name: &str, file: &str| -> std::io::Result<String> {
+        let target = automake_canonical_name(name);
+        let sources = &dep_map[&binary_source(&dep_map, file, cli.source_extension())].0;
+        writeln!(
+            out,
+            "{target} = executable('{target}', {sources}, dependencies : deps)",
+            target = target,
+            sources = sources
+                .iter()
+                .map(|source| format!("'{}'", source))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+        Ok(target)
+    };
 
-    Ok(())
-}
+    for file in &partitioned.standalone {
+        executable(standalone_binary(cli, &partitioned, file).1, file)?;
+    }
+    for file in &partitioned.examples {
+        executable(file, file)?;
+    }
 
-fn generate_source_file_dependencies_variable_for_file(
-    makefile: &mut File,
-    file: &str,
-    ctx: &GenerateContext,
-) -> std::io::Result<()> {
-    let var_name = strip_extension(file);
-    let var_name = source_file_dependencies_var_name(&var_name);
-    write!(makefile, "{} := ", var_name)?;
+    let mut runs = Vec::new();
+    for file in &partitioned.tests {
+        runs.push(format!("test('{}', {})", file, executable(file, file)?));
+    }
+    for file in &partitioned.benchmarks {
+        runs.push(format!(
+            "benchmark('{}', {})",
+            file,
+            executable(file, file)?
+        ));
+    }
 
-    let dependencies = &ctx.dep_map.get(file).unwrap().0;
-    writeln!(makefile, "{}", dependencies.join(" "))?;
+    if !runs.is_empty() {
+        writeln!(out, "\n{}", runs.join("\n"))?;
+    }
 
     Ok(())
 }
 
-fn generate_targets(makefile: &mut File, ctx: &GenerateContext) -> std::io::Result<()> {
-    macro_rules! generate_target {
-        ($makefile:ident, $ctx:ident, $id:ident) => {
-            if $ctx.partitioned.$id.len() > 0 {
-                std::write!($makefile, "{}: ", std::stringify!($id))?;
+// Automake refers to a program's variables with every character that isn't alphanumeric or @ replaced by _
+fn automake_canonical_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '@' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
 
-                for file in &$ctx.partitioned.$id {
-                    std::write!($makefile, "{} ", self::escape_folder(file))?;
-                }
+// Refreshes the dependency variables of the existing Makefile, leaving everything else untouched
+pub fn update_makefile_dependencies(cli: &Cli, parse_result: &ParseResult) -> std::io::Result<()> {
+    let existing = fs::read_to_string("Makefile").map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("Could not read the existing Makefile: {}", e),
+        )
+    })?;
 
-                writeln!(makefile, "\n")?;
+    let mut fresh = Vec::new();
+    write_makefile(&mut fresh, cli, parse_result)?;
+    let fresh = String::from_utf8_lossy(&fresh);
 
-                for file in &$ctx.partitioned.$id {
-                    generate_object_file_dependencies_variable_for_file(
-                        makefile,
-                        &format!("{}.{}", file, ctx.cli.extension),
-                        ctx,
-                    )?;
+    let updated = update_dependency_variables(&existing, &fresh);
+    if updated != existing {
+        fs::write("Makefile", updated)?;
+    }
 
-                    std::writeln!(
-                        $makefile,
-                        "\n{target}: $(ODIR) $({dep_var})\n\
-                            \t$(CC) $(CFLAGS) $({dep_var}) -o {out}\n",
-                        target = self::escape_folder(file),
-                        dep_var = self::object_file_dependencies_var_name(file),
-                        out = file
-                    )?;
-                }
-            }
-        };
+    Ok(())
+}
+
+// Replaces the definitions of the dependency variables in the existing Makefile with the fresh ones.
+// Variables the fresh Makefile doesn't define are left as they are
+fn update_dependency_variables(existing: &str, fresh: &str) -> String {
+    let fresh_variables: HashMap<_, _> = fresh
+        .lines()
+        .filter_map(|line| Some((dependency_variable(line)?, line)))
+        .collect();
+
+    let mut updated = existing
+        .lines()
+        .map(|line| {
+            dependency_variable(line)
+                .and_then(|name| fresh_variables.get(name))
+                .copied()
+                .unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if existing.ends_with('\n') {
+        updated.push('\n');
     }
 
-    writeln!(
-        makefile,
-        "all: binaries\n\n\
-        $(ODIR):\n\
-            \t@mkdir $(ODIR)\n",
-    )?;
+    updated
+}
 
-    // We should always have at least one standalone binary which is the main program
-    write!(makefile, "binaries: ")?;
+// The name of the variable the line defines, if it's one of the dependency variables
+fn dependency_variable(line: &str) -> Option<&str> {
+    let name = &line[..line.find(" := ")?];
+    let is_dependency_variable = !name.contains(char::is_whitespace)
+        && (name.ends_with("_SOURCE_DEPS") || name.ends_with("_OBJECT_DEPS"));
 
-    let main_file = strip_extension(ctx.cli.main_file);
+    Some(name).filter(|_| is_dependency_variable)
+}
 
-    for bin_file in &ctx.partitioned.standalone {
-        let (prefix, name) = if *bin_file != main_file {
-            ("bin_", *bin_file)
-        } else {
-            ("", ctx.cli.binary)
-        };
+// Prints the transitive dependencies of the file, one per line, the way they end up in its variables
+pub fn print_dependencies(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+    file: &str,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let file = file.trim_start_matches("./");
 
-        write!(
-            makefile,
-            "{prefix}{name} ",
-            prefix = prefix,
-            name = escape_folder(name)
-        )?;
+    if !dependency_map.contains_key(file) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not one of the parsed files", file),
+        ));
     }
 
-    writeln!(makefile, "\n")?;
+    for dependency in get_all_file_dependencies(file, cli.source_extension(), &dependency_map) {
+        writeln!(out, "{}", dependency)?;
+    }
 
-    for bin_file in &ctx.partitioned.standalone {
-        generate_object_file_dependencies_variable_for_file(
-            makefile,
-            &format!("{}.{}", bin_file, ctx.cli.extension),
-            ctx,
-        )?;
+    Ok(())
+}
 
-        let (prefix, name) = if *bin_file != main_file {
-            ("bin_", *bin_file)
-        } else {
-            ("", ctx.cli.binary)
-        };
+// Explains how the file ended up in the Makefile (or why it didn't): whether it's a source file,
+// whether it was excluded, which partition it belongs to and what it depends on
+pub fn explain_file(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parser: &Parser,
+    parse_result: &ParseResult,
+    file: &str,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let file = file.trim_start_matches("./");
+    writeln!(out, "{}", file)?;
 
+    let extension = match cli.source_extension() {
+        MIXED_EXTENSION => "extensions of c and c++".to_owned(),
+        extension => format!("extension {}", extension),
+    };
+    if parser.is_source_file(Path::new(file)) {
+        writeln!(out, "    Source file: yes, it matches the {}", extension)?;
+    } else {
         writeln!(
-            makefile,
-            "\n{prefix}{name}: $(ODIR) $({dep_var})\n\
-                    \t$(CC) $(CFLAGS) $({dep_var}) -o {out} $(LFLAGS)\n",
-            prefix = prefix,
-            name = escape_folder(name),
-            dep_var = object_file_dependencies_var_name(bin_file),
-            out = name
+            out,
+            "    Source file: no, it doesn't match the {}",
+            extension
         )?;
     }
 
-    generate_target!(makefile, ctx, tests);
-    generate_target!(makefile, ctx, benchmarks);
-    generate_target!(makefile, ctx, examples);
-
-    for file in ctx
-        .dep_map
-        .keys()
-        .filter(|k| has_extension(k, ctx.cli.extension))
-        .map(|k| strip_extension(k))
+    if cli
+        .exclude_files
+        .iter()
+        .any(|excluded| excluded.trim_start_matches("./") == file)
     {
-        writeln!(
-            makefile,
-            "$(ODIR)/{out}.o: $(ODIR) $({source_var})\n\
-                \t$(CC) -c $(CFLAGS) {file}.{extension} -o $(ODIR)/{out}.o\n",
-            file = file,
-            source_var = source_file_dependencies_var_name(file),
-            extension = ctx.cli.extension,
-            out = escape_folder(file),
-        )?;
+        writeln!(out, "    Excluded: yes, by --exclude-file")?;
+        return Ok(());
+    }
+    if let Some(excluded) = parser.excluded_by(Path::new(file)) {
+        writeln!(out, "    Excluded: yes, by --exclude {}", excluded)?;
+        return Ok(());
     }
+    writeln!(out, "    Excluded: no")?;
 
-    generate_clean_target(makefile, ctx)?;
+    let has_main = match dependency_map.get(file) {
+        Some((_, has_main)) => *has_main,
+        None => {
+            writeln!(
+                out,
+                "    Parsed: no, it's neither a source file nor included by one"
+            )?;
+            return Ok(());
+        }
+    };
+
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+    let name = strip_extension(file);
+    let partition = if !has_main && is_translation_unit(cli, file) {
+        "none, it has no main function and is linked into the programs depending on it".to_owned()
+    } else if !has_main {
+        "none, it's a header".to_owned()
+    } else if partitioned.tests.contains(&name) {
+        "tests".to_owned()
+    } else if partitioned.benchmarks.contains(&name) {
+        "benchmarks".to_owned()
+    } else if partitioned.examples.contains(&name) {
+        "examples".to_owned()
+    } else {
+        format!(
+            "standalone, built as {}",
+            standalone_binary(cli, &partitioned, name).1
+        )
+    };
+    writeln!(out, "    Partition: {}", partition)?;
+
+    let dependencies: Vec<_> =
+        get_all_file_dependencies(file, cli.source_extension(), &dependency_map)
+            .into_iter()
+            .filter(|dependency| dependency != file)
+            .collect();
+    if dependencies.is_empty() {
+        writeln!(out, "    Dependencies: none")
+    } else {
+        writeln!(out, "    Dependencies: {}", dependencies.join(" "))
+    }
+}
+
+// Reports the translation units pulling in more headers than the given limit, as they are likely slow to compile
+pub fn long_compile_units(cli: &Cli, parse_result: &ParseResult, limit: usize) -> Vec<String> {
+    let dependency_map = &parse_result.dependency_map;
+
+    dependency_map
+        .keys()
+        .filter(|file| is_translation_unit(cli, file))
+        .filter_map(|file| {
+            let headers = get_all_file_dependencies(file, cli.source_extension(), dependency_map)
+                .iter()
+                .filter(|d| !is_translation_unit(cli, d))
+                .count();
+
+            if headers > limit {
+                Some(format!(
+                    "{} includes {} headers (more than {}), consider a precompiled header",
+                    file, headers, limit
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Prints the files (without their extension) each partition is made of, to debug their classification
+pub fn print_partitions(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+
+    let partitions = [
+        ("standalone", &partitioned.standalone),
+        ("tests", &partitioned.tests),
+        ("benchmarks", &partitioned.benchmarks),
+        ("examples", &partitioned.examples),
+    ];
+    for (name, files) in partitions.iter() {
+        writeln!(out, "{}:", name)?;
+        for file in files.iter() {
+            writeln!(out, "    {}", file)?;
+        }
+    }
 
     Ok(())
 }
 
-fn generate_clean_target(makefile: &mut File, ctx: &GenerateContext) -> std::io::Result<()> {
-    write!(
-        makefile,
-        ".PHONY: clean\n\
-        clean:\n\
-            \trm -rf .OBJ ",
-    )?;
+// Prints a one line summary of what the Makefile builds, e.g.
+// Program foo from 2 sources, 3 tests, links -lm -lpthread
+pub fn print_plan(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let dep_map = flatten_dependencies(&dependency_map, cli);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
 
-    let main_file = strip_extension(ctx.cli.main_file);
+    // The main program comes first
+    let mut programs = partitioned.standalone.clone();
+    programs.sort_by_key(|file| *file != partitioned.main_file);
 
-    let all_files = ctx
-        .partitioned
-        .standalone
+    let mut plan: Vec<_> = programs
         .iter()
-        .map(|f| if *f != main_file { f } else { &ctx.cli.binary })
-        .chain(ctx.partitioned.tests.iter())
-        .chain(ctx.partitioned.benchmarks.iter())
-        .chain(ctx.partitioned.examples.iter());
+        .map(|file| {
+            let (_, name) = standalone_binary(cli, &partitioned, file);
+            let sources = dep_map[&binary_source(&dep_map, file, cli.source_extension())]
+                .0
+                .iter()
+                .filter(|d| dep_map.contains_key(*d))
+                .count();
+            format!("Program {} from {}", name, plural(sources, "source"))
+        })
+        .collect();
 
-    for file in all_files {
-        write!(makefile, "{} ", file)?;
+    let partitions = [
+        (partitioned.tests.len(), "test"),
+        (partitioned.benchmarks.len(), "benchmark"),
+        (partitioned.examples.len(), "example"),
+    ];
+    for (count, noun) in partitions.iter().filter(|(count, _)| *count > 0) {
+        plan.push(plural(*count, noun));
     }
 
-    writeln!(makefile)?;
+    if !parse_result.dlls.is_empty() {
+        let libraries: Vec<_> = parse_result
+            .dlls
+            .iter()
+            .map(|dll| format!("-l{}", dll))
+            .collect();
+        plan.push(format!("links {}", libraries.join(" ")));
+    }
 
-    Ok(())
+    writeln!(out, "{}", plan.join(", "))
 }
 
-#[inline]
-fn escape_folder(filename: &str) -> String {
-    filename.replace('/', "_")
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
 }
 
-#[inline]
-fn file_dependencies_var_name(filename: &str, category: &str) -> String {
-    let var_name = escape_folder(filename);
-    format!("{}_{}_DEPS", var_name.to_ascii_uppercase(), category)
+pub fn generate_dot_file(parse_result: &ParseResult) -> std::io::Result<()> {
+    let mut dot = Vec::new();
+    write_dot(&mut dot, &parse_result.dependency_map)?;
+    fs::write(DOT_FILE, dot)
 }
 
-#[inline]
-fn source_file_dependencies_var_name(filename: &str) -> String {
-    file_dependencies_var_name(filename, "SOURCE")
+const DOT_FILE: &str = "deps.dot";
+
+const COMPILE_FLAGS_FILE: &str = "compile_flags.txt";
+
+const BENCH_RESULTS_DIR: &str = "bench-results";
+
+// Writes the compiler flags for clangd, one argument per line
+pub fn generate_compile_flags(cli: &Cli) -> std::io::Result<()> {
+    let mut flags = compile_flags(cli).join("\n");
+    flags.push('\n');
+    fs::write(COMPILE_FLAGS_FILE, flags)
 }
 
-#[inline]
-fn object_file_dependencies_var_name(filename: &str) -> String {
-    file_dependencies_var_name(filename, "OBJECT")
+// The flags every file is compiled with, as far as they are known without running make
+fn compile_flags(cli: &Cli) -> Vec<String> {
+    let mut flags = vec!["-Wall".to_owned()];
+    if let Some(standard) = cli.standard {
+        flags.push(format!("-std={}", standard));
+    }
+    flags.push(format!("-{}", cli.opt_level));
+
+    if cli.freestanding {
+        flags.push("-ffreestanding".to_owned());
+    }
+
+    if cli.lto {
+        flags.push("-flto".to_owned());
+    }
+
+    if cli.pie {
+        flags.push("-fPIE".to_owned());
+    }
+
+    if cli.lib == Some("shared") {
+        flags.push("-fPIC".to_owned());
+    }
+
+    if cli.modules {
+        flags.push("-fmodules-ts".to_owned());
+    }
+
+    for dir in &cli.iquote {
+        flags.push("-iquote".to_owned());
+        flags.push(dir.to_string());
+    }
+
+    for dir in &cli.include_dirs {
+        flags.push(format!("-I{}", dir));
+    }
+
+    for define in &cli.defines {
+        flags.push(format!("-D{}", define));
+    }
+
+    if let Some(extra) = cli.cflags {
+        flags.extend(extra.split_whitespace().map(str::to_owned));
+    }
+
+    flags
+}
+
+pub fn generate_summary_json(
+    cli: &Cli,
+    parse_result: &ParseResult,
+    path: &str,
+) -> std::io::Result<()> {
+    let mut summary = Vec::new();
+    write_summary_json(&mut summary, cli, parse_result)?;
+    fs::write(path, summary)
+}
+
+// Writes a report of the generated targets and the warnings of the run for CI systems to consume
+fn write_summary_json(
+    out: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let dep_map = flatten_dependencies(&dependency_map, cli);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+
+    let binaries: Vec<_> = partitioned
+        .standalone
+        .iter()
+        .map(|f| standalone_binary(cli, &partitioned, f).0)
+        .collect();
+    let escaped = |files: &[&str]| -> Vec<_> { files.iter().map(|f| escape_folder(f)).collect() };
+
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"binary_count\": {},", binaries.len())?;
+    writeln!(out, "  \"binaries\": {},", json_array(&binaries))?;
+    writeln!(
+        out,
+        "  \"tests\": {},",
+        json_array(&escaped(&partitioned.tests))
+    )?;
+    writeln!(
+        out,
+        "  \"benchmarks\": {},",
+        json_array(&escaped(&partitioned.benchmarks))
+    )?;
+    writeln!(
+        out,
+        "  \"examples\": {},",
+        json_array(&escaped(&partitioned.examples))
+    )?;
+    writeln!(out, "  \"object_count\": {},", dep_map.len())?;
+    writeln!(out, "  \"libraries\": {},", json_array(&parse_result.dlls))?;
+    writeln!(
+        out,
+        "  \"warnings\": {}",
+        json_array(&parse_result.warnings)
+    )?;
+    writeln!(out, "}}")
+}
+
+fn json_array(values: &[String]) -> String {
+    let values: Vec<_> = values.iter().map(|v| json_string(v)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+// Writes the include graph in Graphviz's DOT format
+fn write_dot(out: &mut dyn Write, dep_map: &DependencyMap) -> std::io::Result<()> {
+    writeln!(out, "digraph dependencies {{")?;
+
+    for (file, (dependencies, _)) in dep_map {
+        if dependencies.is_empty() {
+            writeln!(out, "    \"{}\";", file)?;
+        }
+
+        for dependency in dependencies {
+            writeln!(out, "    \"{}\" -> \"{}\";", file, dependency)?;
+        }
+    }
+
+    writeln!(out, "}}")
+}
+
+fn write_makefile(
+    makefile: &mut dyn Write,
+    cli: &Cli,
+    parse_result: &ParseResult,
+) -> std::io::Result<()> {
+    let dependency_map = emitted_dependency_map(cli, parse_result);
+    let dep_map = flatten_dependencies(&dependency_map, cli);
+    let partitioned = PartitionedFiles::partition(cli, &dependency_map);
+    let ctx = GenerateContext::new(cli, &partitioned, &dep_map, &parse_result.dlls);
+    ensure_unique_flat_names(&ctx)?;
+    ensure_unique_objects(&ctx)?;
+    ensure_object_recipes_known(&ctx)?;
+    ensure_test_runner_has_main(&ctx)?;
+
+    generate_compiler_variables(makefile, &ctx)?;
+    generate_file_variables(makefile, &ctx)?;
+    generate_targets(makefile, &ctx)?;
+
+    Ok(())
+}
+
+fn generate_compiler_variables(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    // Must come first, before any other makefile is included
+    if ctx.cli.relocatable {
+        writeln!(makefile, "SRCDIR := $(dir $(lastword $(MAKEFILE_LIST)))\n")?;
+    }
+
+    // Don't leave truncated targets behind that would look up to date on the next run
+    writeln!(makefile, ".DELETE_ON_ERROR:\n")?;
+
+    // The rules of the fragments come first, so all has to be made the default goal explicitly
+    if !ctx.cli.include_mks.is_empty() {
+        for pattern in &ctx.cli.include_mks {
+            writeln!(
+                makefile,
+                "-include $(wildcard {})",
+                source_path(ctx, pattern)
+            )?;
+        }
+        writeln!(makefile, ".DEFAULT_GOAL := all\n")?;
+    }
+
+    // In mixed projects the compiler is the C++ one, and the standard goes to the flags of each language
+    let compiler = if ctx.cli.mixed {
+        format!(
+            "{prefix}{c_compiler}\nCXX := {prefix}{compiler}",
+            prefix = ctx.cli.toolchain_prefix,
+            c_compiler = c_compiler(ctx.cli.compiler),
+            compiler = ctx.cli.compiler
+        )
+    } else {
+        format!("{}{}", ctx.cli.toolchain_prefix, ctx.cli.compiler)
+    };
+
+    writeln!(
+        makefile,
+        "CC := {compiler}\n\
+        CFLAGS := -Wall\n\
+        {std}\
+        CFLAGS += -{opt}\n\
+        LFLAGS := {link_flags}",
+        compiler = compiler,
+        std = ctx
+            .cli
+            .standard
+            .filter(|_| !ctx.cli.mixed)
+            .map(|standard| format!("CFLAGS += -std={}\n", standard))
+            .unwrap_or_default(),
+        opt = ctx.cli.opt_level,
+        link_flags = ctx
+            .dlls
+            .iter()
+            .map(|dll| format!("-l{}", dll))
+            .collect::<Vec<_>>()
+            .join(" ")
+    )?;
+
+    if let Some(source) = ctx.cli.version_from {
+        let version = if source == "git" {
+            "git describe --tags".to_owned()
+        } else {
+            format!("cat {}", source_path(ctx, source))
+        };
+
+        // The escaped quotes reach the compiler as "...", so the macro expands to a string literal
+        writeln!(
+            makefile,
+            "VERSION := $(shell {})\n\
+            CFLAGS += -DVERSION=\\\"$(VERSION)\\\"",
+            version
+        )?;
+    }
+
+    if ctx.cli.freestanding {
+        writeln!(makefile, "CFLAGS += -ffreestanding\nLFLAGS += -nostdlib")?;
+    }
+
+    // The binaries are linked with $(CFLAGS) as well, so the optimization happens at link time
+    if ctx.cli.lto {
+        writeln!(makefile, "CFLAGS += -flto")?;
+    }
+
+    if let Some(linker) = ctx.cli.linker {
+        writeln!(makefile, "LINKER := {}", linker)?;
+    }
+
+    // Set by the sanitizer targets when they run make again
+    if ctx.cli.sanitizers {
+        writeln!(makefile, "CFLAGS += $(SANITIZE_FLAGS)")?;
+    }
+
+    // Appended after the optimization level, so -O0 takes precedence
+    if ctx.cli.debug_switch {
+        writeln!(
+            makefile,
+            "DEBUG ?= 0\n\
+            ifeq ($(DEBUG),1)\n\
+            MODE := debug\n\
+            CFLAGS += -O0 -g\n\
+            else\n\
+            MODE := release\n\
+            endif"
+        )?;
+    }
+
+    if ctx.cli.build_profiles {
+        writeln!(
+            makefile,
+            "BUILD ?= debug\n\
+            ifeq ($(BUILD),release)\n\
+            CFLAGS += -O2 -DNDEBUG\n\
+            else\n\
+            CFLAGS += -g\n\
+            endif"
+        )?;
+    }
+
+    if let Some(framework) = ctx.cli.test_framework {
+        let (cflags, lflags) = test_framework_flags(framework);
+        writeln!(
+            makefile,
+            "TEST_CFLAGS := {cflags}\n\
+            TEST_LFLAGS := {lflags}",
+            cflags = cflags,
+            lflags = lflags
+        )?;
+    }
+
+    for library in &ctx.cli.whole_archives {
+        writeln!(
+            makefile,
+            "LFLAGS += -Wl,--whole-archive {library} -Wl,--no-whole-archive",
+            library = library_flag(library)
+        )?;
+    }
+
+    for group in &ctx.cli.link_groups {
+        let libraries: Vec<_> = group.iter().map(|library| library_flag(library)).collect();
+        writeln!(
+            makefile,
+            "LFLAGS += -Wl,--start-group {libraries} -Wl,--end-group",
+            libraries = libraries.join(" ")
+        )?;
+    }
+
+    // Quoted, so that the shell leaves $ORIGIN for the dynamic loader
+    for dir in &ctx.cli.rpaths {
+        writeln!(makefile, "LFLAGS += {}", rpath_flag(dir))?;
+    }
+
+    if let Some(flags) = ctx.cli.ldflags {
+        writeln!(makefile, "LFLAGS += {}", flags)?;
+    }
+
+    if ctx.cli.modules {
+        writeln!(makefile, "CFLAGS += -fmodules-ts")?;
+    }
+
+    if ctx.cli.pie {
+        writeln!(makefile, "CFLAGS += -fPIE")?;
+    }
+
+    // Every object may end up in the shared library
+    if ctx.cli.lib == Some("shared") {
+        writeln!(makefile, "CFLAGS += -fPIC")?;
+    }
+
+    for dir in &ctx.cli.iquote {
+        writeln!(makefile, "CFLAGS += -iquote {}", source_path(ctx, dir))?;
+    }
+
+    for dir in &ctx.cli.include_dirs {
+        writeln!(makefile, "CFLAGS += -I{}", source_path(ctx, dir))?;
+    }
+
+    for define in &ctx.cli.defines {
+        writeln!(makefile, "CFLAGS += -D{}", define)?;
+    }
+
+    for package in &ctx.cli.pkg_config {
+        writeln!(
+            makefile,
+            "CFLAGS += $(shell pkg-config --cflags {package})\n\
+            LFLAGS += $(shell pkg-config --libs {package})",
+            package = package
+        )?;
+    }
+
+    // Last, so that the user's flags override the generated ones
+    if let Some(flags) = ctx.cli.cflags {
+        writeln!(makefile, "CFLAGS += {}", flags)?;
+    }
+
+    // Everything up to here applies to both languages
+    if ctx.cli.mixed {
+        writeln!(
+            makefile,
+            "CXXFLAGS := $(CFLAGS){cxx_std}",
+            cxx_std = ctx
+                .cli
+                .standard
+                .map(|standard| format!(" -std={}", standard))
+                .unwrap_or_default()
+        )?;
+        if let Some(standard) = ctx.cli.c_standard {
+            writeln!(makefile, "CFLAGS += -std={}", standard)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Every source and header the parser came across, sorted
+fn parsed_files<'c>(ctx: &'c GenerateContext) -> impl Iterator<Item = &'c String> {
+    ctx.dep_map
+        .iter()
+        .flat_map(|(file, (dependencies, _))| std::iter::once(file).chain(dependencies))
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+}
+
+fn generate_file_variables(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    writeln!(makefile, "\nODIR := .OBJ\n")?;
+
+    if let Some(depfile_dir) = ctx.cli.depfile_dir {
+        writeln!(makefile, "DEPDIR := {}\n", depfile_dir)?;
+    }
+
+    // The translation units, for the targets that operate on all of them
+    if ctx.cli.lint_target || ctx.cli.tidy_target {
+        let sources = ctx
+            .dep_map
+            .keys()
+            .map(|f| source_path(ctx, f))
+            .collect::<Vec<_>>();
+        writeln!(makefile, "SOURCES := {}\n", sources.join(" "))?;
+    }
+
+    // Every parsed source and header along with the Makefile itself, for packaging
+    if ctx.cli.dist {
+        let files = std::iter::once("Makefile")
+            .chain(parsed_files(ctx).map(String::as_str))
+            .map(|f| source_path(ctx, f))
+            .collect::<Vec<_>>();
+        writeln!(makefile, "DIST_FILES := {}\n", files.join(" "))?;
+    }
+
+    // The generated files aren't ours to format
+    if ctx.cli.format_targets {
+        let files = parsed_files(ctx)
+            .filter(|f| !ctx.cli.generated.iter().any(|(output, _)| output == f))
+            .map(|f| source_path(ctx, f))
+            .collect::<Vec<_>>();
+        writeln!(makefile, "FORMAT_FILES := {}\n", files.join(" "))?;
+    }
+
+    // The compiler's depfiles take their place
+    if ctx.cli.depmode != "compiler" {
+        for file in ctx.dep_map.keys() {
+            generate_source_file_dependencies_variable_for_file(makefile, file, ctx)?;
+        }
+    }
+
+    writeln!(makefile)?;
+
+    Ok(())
+}
+
+fn generate_object_file_dependencies_variable_for_file(
+    makefile: &mut dyn Write,
+    file: &str,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    let var_name = flat_name(ctx, strip_extension(file));
+    let var_name = object_file_dependencies_var_name(var_name);
+    write!(makefile, "{} := ", var_name)?;
+
+    let mut dependencies = ctx.dep_map.get(file).unwrap().0.clone();
+    if is_test_runner(ctx.cli, file) {
+        for test_file in runner_test_files(ctx) {
+            for dependency in &ctx.dep_map[test_file].0 {
+                if !dependencies.contains(dependency) {
+                    dependencies.push(dependency.clone());
+                }
+            }
+        }
+    }
+
+    let translation_units = dependencies.iter().filter(|d| ctx.dep_map.contains_key(*d));
+    let object_dependencies = match ctx.cli.static_lib.filter(|_| ctx.cli.lib.is_some()) {
+        // In library mode the library code is linked from the archive, after the program's own objects
+        Some(name) => {
+            let (library, own): (Vec<_>, Vec<_>) =
+                translation_units.partition(|d| is_library_source(ctx, d));
+            own.iter()
+                .map(|d| object_file(ctx, &unit_name(d)))
+                .chain((!library.is_empty()).then(|| static_lib(name)))
+                .collect::<Vec<_>>()
+        }
+        None => translation_units
+            .map(|d| object_file(ctx, &unit_name(d)))
+            .collect::<Vec<_>>(),
+    }
+    .join(" ");
+
+    writeln!(makefile, "{}", object_dependencies)?;
+
+    Ok(())
+}
+
+fn generate_source_file_dependencies_variable_for_file(
+    makefile: &mut dyn Write,
+    file: &str,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    let var_name = unit_name(file);
+    let var_name = source_file_dependencies_var_name(flat_name(ctx, &var_name));
+    write!(makefile, "{} := ", var_name)?;
+
+    let dependencies = ctx.dep_map.get(file).unwrap().0.iter();
+    let dependencies = dependencies
+        .map(|d| source_path(ctx, d))
+        .collect::<Vec<_>>();
+    writeln!(makefile, "{}", dependencies.join(" "))?;
+
+    Ok(())
+}
+
+fn generate_targets(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    macro_rules! generate_target {
+        ($makefile:ident, $ctx:ident, $id:ident) => {
+            generate_target!($makefile, $ctx, $id, "", "")
+        };
+        ($makefile:ident, $ctx:ident, $id:ident, $cflags:expr, $lflags:expr) => {
+            if $ctx.partitioned.$id.len() > 0 {
+                std::write!($makefile, "{}: ", std::stringify!($id))?;
+
+                for file in &$ctx.partitioned.$id {
+                    std::write!($makefile, "{} ", self::escape_folder(file))?;
+                }
+
+                writeln!(makefile, "\n")?;
+
+                for file in &$ctx.partitioned.$id {
+                    generate_object_file_dependencies_variable_for_file(
+                        makefile,
+                        &self::binary_source(ctx.dep_map, file, ctx.cli.source_extension()),
+                        ctx,
+                    )?;
+
+                    std::writeln!(
+                        $makefile,
+                        "\n{target}: $({dep_var}){extra}{mode_stamp} | $(ODIR)\n\
+                            \t{link} {link_cflags}{cflags} $({dep_var}) -o {out}{lflags}{binary_lflags}\n",
+                        mode_stamp = self::mode_stamp($ctx),
+                        link_cflags = self::link_cflags($ctx),
+                        link = self::link_command($ctx),
+                        target = self::escape_folder(file),
+                        extra = self::extra_prerequisites($ctx),
+                        cflags = $cflags,
+                        lflags = $lflags,
+                        binary_lflags = self::binary_lflags($ctx, &[&self::escape_folder(file), file]),
+                        dep_var = self::object_file_dependencies_var_name(self::flat_name($ctx, file)),
+                        out = file
+                    )?;
+                }
+            }
+        };
+    }
+
+    let mut objcopy_outputs: String = ctx
+        .cli
+        .objcopy
+        .iter()
+        .map(|format| format!(" {}", objcopy_output(ctx, format, binary_suffix(ctx))))
+        .collect();
+    for lib in libraries(ctx) {
+        objcopy_outputs.push_str(&format!(" {}", lib));
+    }
+
+    writeln!(
+        makefile,
+        "all: binaries{objcopy_outputs}\n\n\
+        $(ODIR):\n\
+            \t@mkdir -p $(ODIR)\n",
+        objcopy_outputs = objcopy_outputs
+    )?;
+
+    if ctx.cli.depfile_dir.is_some() {
+        writeln!(makefile, "$(DEPDIR):\n\t@mkdir -p $(DEPDIR)\n")?;
+    }
+
+    generate_compiler_stamp(makefile, ctx)?;
+    generate_mode_stamp(makefile, ctx)?;
+
+    ensure_unique_programs(ctx)?;
+    ensure_sources_not_overwritten(ctx)?;
+
+    // We should always have at least one standalone binary which is the main program
+    write!(makefile, "binaries: ")?;
+
+    for bin_file in &ctx.partitioned.standalone {
+        let (target, _) = standalone_binary(ctx.cli, ctx.partitioned, bin_file);
+        write!(makefile, "{}{} ", target, binary_suffix(ctx))?;
+    }
+
+    writeln!(makefile, "\n")?;
+
+    generate_language_targets(makefile, ctx)?;
+
+    for bin_file in &ctx.partitioned.standalone {
+        generate_object_file_dependencies_variable_for_file(
+            makefile,
+            &binary_source(ctx.dep_map, bin_file, ctx.cli.source_extension()),
+            ctx,
+        )?;
+
+        let (target, out) = standalone_binary(ctx.cli, ctx.partitioned, bin_file);
+
+        writeln!(
+            makefile,
+            "\n{target}{suffix}: $({dep_var}){extra}{mode_stamp} | $(ODIR)\n\
+                    \t{link} {link_cflags} $({dep_var}) -o {out}{suffix} $(LFLAGS){pie}{binary_lflags}\n",
+            suffix = binary_suffix(ctx),
+            mode_stamp = mode_stamp(ctx),
+            link_cflags = link_cflags(ctx),
+            link = link_command(ctx),
+            target = target,
+            pie = if ctx.cli.pie { " -pie" } else { "" },
+            binary_lflags = binary_lflags(ctx, &[&target, out]),
+            extra = extra_prerequisites(ctx),
+            dep_var = object_file_dependencies_var_name(flat_name(ctx, bin_file)),
+            out = out
+        )?;
+    }
+
+    if ctx.cli.test_framework.is_some() {
+        generate_target!(makefile, ctx, tests, " $(TEST_CFLAGS)", " $(TEST_LFLAGS)");
+    } else {
+        generate_target!(makefile, ctx, tests);
+    }
+    generate_check_target(makefile, ctx)?;
+    generate_test_run_targets(makefile, ctx)?;
+    generate_run_benchmarks_target(makefile, ctx)?;
+    generate_memcheck_target(makefile, ctx)?;
+    generate_target!(makefile, ctx, benchmarks);
+    generate_target!(makefile, ctx, examples);
+    generate_run_target(makefile, ctx)?;
+    generate_link_jobs(makefile, ctx)?;
+
+    for source in object_rule_order(ctx) {
+        let file = unit_name(source);
+        // The C++ sources of mixed projects are compiled with the C++ compiler and flags
+        let (compile, flags) = if ctx.cli.mixed && !has_extension(source, "c") {
+            ("$(CXX)", "$(CXXFLAGS)")
+        } else {
+            ("$(CC)", "$(CFLAGS)")
+        };
+        let cflags =
+            if ctx.cli.test_framework.is_some() && contained_in_partition(&ctx.cli.tests, &file) {
+                format!("{} $(TEST_CFLAGS)", flags)
+            } else {
+                flags.to_owned()
+            };
+
+        // Module interfaces must be compiled before the files importing them
+        let interfaces: String = ctx.dep_map[source]
+            .0
+            .iter()
+            .filter(|d| *d != source && is_module_interface(d))
+            .map(|d| format!(" {}", object_file(ctx, &unit_name(d))))
+            .collect();
+
+        // Compiled by the pattern rules, so only the prerequisites besides the source are needed
+        if is_pattern_compiled(ctx, source) {
+            let prerequisites = format!("{}{}", object_sources(ctx, source, &file), interfaces);
+            if prerequisites != source_path(ctx, source) {
+                writeln!(makefile, "{}: {}", object_file(ctx, &file), prerequisites)?;
+            }
+            continue;
+        }
+
+        // Compilers don't recognize the module interface extensions as C++ by themselves,
+        // nor the language of files without an extension
+        let language = if is_module_interface(source) {
+            "-x c++ "
+        } else if is_extensionless(source) && ctx.cli.extension == "c" {
+            "-x c "
+        } else if is_extensionless(source) {
+            "-x c++ "
+        } else {
+            ""
+        };
+
+        writeln!(
+            makefile,
+            "{out}: {sources}{interfaces}{extra}{compiler} | $(ODIR){depdir}\n\
+                {mkdir}{echo}\t{recipe}\n",
+            compiler = if ctx.cli.compiler_dep {
+                " $(COMPILER_STAMP)"
+            } else {
+                ""
+            },
+            // The directories are order-only, as their timestamps change whenever a file is written in them,
+            // which would otherwise rebuild the objects that were made before
+            depdir = if ctx.cli.depfile_dir.is_some() {
+                " $(DEPDIR)"
+            } else {
+                ""
+            },
+            mkdir = object_dirs(ctx, &file),
+            echo = if ctx.cli.echo_includes {
+                format!(
+                    "\t@echo \"$@: {}\" >&2\n",
+                    object_sources(ctx, source, &file)
+                )
+            } else {
+                String::new()
+            },
+            recipe = match object_recipe(ctx, source) {
+                Some(recipe) => recipe.to_owned(),
+                None => format!(
+                    "{compile} -c {cflags}{depfile_flags} {language}{source} -o {out}",
+                    compile = compile,
+                    cflags = cflags,
+                    depfile_flags = depfile_flags(ctx, &file),
+                    language = language,
+                    source = source_path(ctx, source),
+                    out = object_file(ctx, &file),
+                ),
+            },
+            interfaces = interfaces,
+            extra = extra_prerequisites(ctx),
+            sources = object_sources(ctx, source, &file),
+            out = object_file(ctx, &file),
+        )?;
+    }
+
+    generate_pattern_rules(makefile, ctx)?;
+    generate_generated_files(makefile, ctx)?;
+    generate_objects_target(makefile, ctx)?;
+    generate_fuzz_target(makefile, ctx)?;
+    generate_objcopy_targets(makefile, ctx)?;
+    generate_static_lib_target(makefile, ctx)?;
+    generate_shared_lib_target(makefile, ctx)?;
+    generate_lint_target(makefile, ctx)?;
+    generate_tidy_target(makefile, ctx)?;
+    generate_format_targets(makefile, ctx)?;
+    generate_tags_target(makefile, ctx)?;
+    generate_compdb_target(makefile, ctx)?;
+    generate_dist_target(makefile, ctx)?;
+    generate_dep_graph_target(makefile, ctx)?;
+    generate_sanitizer_targets(makefile, ctx)?;
+    generate_install_targets(makefile, ctx)?;
+    generate_clean_target(makefile, ctx)?;
+
+    if ctx.cli.auto_deps {
+        let dir = if ctx.cli.depfile_dir.is_some() {
+            "$(DEPDIR)"
+        } else {
+            object_dir(ctx)
+        };
+
+        if ctx.cli.mirror_dirs {
+            // make's wildcard doesn't descend into the mirrored directories by itself
+            writeln!(
+                makefile,
+                "\nrwildcard = $(foreach d,$(wildcard $(1:=/*)),$(call rwildcard,$d,$2) $(filter $(subst *,%,$2),$d))\n\
+                -include $(call rwildcard,{},*.d)",
+                dir
+            )?;
+        } else {
+            writeln!(makefile, "\n-include $(wildcard {}/*.d)", dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Make has no way to limit the jobs of some rules only, so the binaries are split into N chains
+// in which each one waits for the previous one (order-only) before being linked.
+// Building a binary by name builds the ones before it in its chain as well
+fn generate_link_jobs(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    let jobs = match ctx.cli.link_jobs {
+        Some(jobs) => jobs,
+        None => return Ok(()),
+    };
+
+    let partitioned = ctx.partitioned;
+    let binaries: Vec<_> = partitioned
+        .standalone
+        .iter()
+        .map(|file| standalone_binary(ctx.cli, partitioned, file).0)
+        .chain(
+            partitioned
+                .tests
+                .iter()
+                .chain(&partitioned.benchmarks)
+                .chain(&partitioned.examples)
+                .map(|file| escape_folder(file)),
+        )
+        .collect();
+
+    for (previous, binary) in binaries.iter().zip(binaries.iter().skip(jobs)) {
+        writeln!(makefile, "{}: | {}", binary, previous)?;
+    }
+
+    if binaries.len() > jobs {
+        writeln!(makefile)?;
+    }
+
+    Ok(())
+}
+
+// In mixed projects, all-c and all-cpp build only the binaries whose main is written in that language
+fn generate_language_targets(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if !ctx.cli.mixed {
+        return Ok(());
+    }
+
+    for (target, language) in &[("all-c", "c"), ("all-cpp", "cpp")] {
+        let binaries: Vec<_> = ctx
+            .partitioned
+            .standalone
+            .iter()
+            .filter(|file| {
+                let source = binary_source(ctx.dep_map, file, ctx.cli.source_extension());
+                has_extension(source, language)
+            })
+            .map(|file| {
+                let (target, _) = standalone_binary(ctx.cli, ctx.partitioned, file);
+                format!("{}{}", target, binary_suffix(ctx))
+            })
+            .collect();
+
+        writeln!(
+            makefile,
+            ".PHONY: {target}\n\
+            {target}: {binaries}\n",
+            target = target,
+            binaries = binaries.join(" ")
+        )?;
+    }
+
+    Ok(())
+}
+
+// Builds and runs any of the binaries by its target, e.g. make run-foo ARGS="--verbose",
+// or the main program with make run
+fn generate_run_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    let main_file = ctx.partitioned.main_file.as_str();
+    if ctx.partitioned.standalone.contains(&main_file) {
+        let (target, out) = standalone_binary(ctx.cli, ctx.partitioned, main_file);
+        writeln!(
+            makefile,
+            ".PHONY: run\n\
+            run: {target}\n\
+            \t./{out} $(ARGS)\n",
+            target = target,
+            out = out
+        )?;
+    }
+
+    // The targets of the binaries outside the root directory aren't their outputs, so each gets its own rule
+    let programs = ctx
+        .partitioned
+        .standalone
+        .iter()
+        .map(|file| standalone_binary(ctx.cli, ctx.partitioned, file));
+    let others = ctx
+        .partitioned
+        .tests
+        .iter()
+        .chain(&ctx.partitioned.benchmarks)
+        .chain(&ctx.partitioned.examples)
+        .map(|file| (escape_folder(file), *file));
+
+    for (target, out) in programs.chain(others) {
+        writeln!(
+            makefile,
+            ".PHONY: run-{target}\n\
+            run-{target}: {target}\n\
+            \t./{out} $(ARGS)\n",
+            target = target,
+            out = out
+        )?;
+    }
+
+    Ok(())
+}
+
+fn generate_check_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if ctx.partitioned.tests.is_empty() {
+        return Ok(());
+    }
+
+    // Stops at the first failing test, unless asked to keep going with --test-keep-going or make -k.
+    // Either way every test run is reported, followed by the number of passed and failed ones
+    let stop = if ctx.cli.test_keep_going {
+        ""
+    } else {
+        "\t\t\t[ -n \"$(findstring k,$(firstword -$(MAKEFLAGS)))\" ] || break; \\\n"
+    };
+
+    writeln!(
+        makefile,
+        ".PHONY: check run-tests\n\
+        check run-tests: tests\n\
+            \t@passed=0; failed=0; \\\n\
+            \tfor test in {tests}; do \\\n\
+            \t\tif ./$$test; then \\\n\
+            \t\t\techo \"PASS: $$test\"; passed=$$((passed + 1)); \\\n\
+            \t\telse \\\n\
+            \t\t\techo \"FAIL: $$test\"; failed=$$((failed + 1)); \\\n\
+            {stop}\
+            \t\tfi; \\\n\
+            \tdone; \\\n\
+            \techo \"$$passed passed, $$failed failed\"; \\\n\
+            \t[ $$failed -eq 0 ]\n",
+        tests = ctx.partitioned.tests.join(" "),
+        stop = stop
+    )?;
+
+    Ok(())
+}
+
+// Runs the benchmarks one after the other, keeping the output of each run in
+// bench-results/<benchmark>-<timestamp>.txt so that runs can be compared
+fn generate_run_benchmarks_target(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if ctx.partitioned.benchmarks.is_empty() {
+        return Ok(());
+    }
+
+    let runs: Vec<_> = ctx
+        .partitioned
+        .benchmarks
+        .iter()
+        .map(|bench| {
+            format!(
+                "\t./{bench} > {dir}/{name}-$$stamp.txt && cat {dir}/{name}-$$stamp.txt",
+                bench = bench,
+                dir = BENCH_RESULTS_DIR,
+                name = escape_folder(bench)
+            )
+        })
+        .collect();
+
+    writeln!(
+        makefile,
+        ".PHONY: run-benchmarks\n\
+        run-benchmarks: benchmarks\n\
+            \t@mkdir -p {dir}\n\
+            \t@stamp=$$(date +%Y%m%d-%H%M%S); \\\n\
+            {runs}\n",
+        dir = BENCH_RESULTS_DIR,
+        runs = runs.join(" && \\\n")
+    )
+}
+
+// Runs the main program (with the ARGS of make run) and every test under valgrind, failing on any error or leak
+fn generate_memcheck_target(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if !ctx.cli.valgrind {
+        return Ok(());
+    }
+
+    let mut prerequisites = Vec::new();
+    let mut runs = Vec::new();
+
+    let main_file = ctx.partitioned.main_file.as_str();
+    if ctx.partitioned.standalone.contains(&main_file) {
+        let (target, out) = standalone_binary(ctx.cli, ctx.partitioned, main_file);
+        prerequisites.push(target);
+        runs.push(format!("\t$(VALGRIND) ./{} $(ARGS)", out));
+    }
+
+    if !ctx.partitioned.tests.is_empty() {
+        prerequisites.push("tests".to_owned());
+        for test in &ctx.partitioned.tests {
+            runs.push(format!("\t$(VALGRIND) ./{}", test));
+        }
+    }
+
+    writeln!(
+        makefile,
+        "VALGRIND := valgrind --error-exitcode=1 --leak-check=full\n\n\
+        .PHONY: memcheck\n\
+        memcheck: {prerequisites}\n\
+        {runs}\n",
+        prerequisites = prerequisites.join(" "),
+        runs = runs.join("\n")
+    )
+}
+
+// Builds and runs a single test by name, e.g. make test-foo for tests/foo.
+// Tests sharing a name in different directories are named after their whole path instead
+fn generate_test_run_targets(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    let tests = &ctx.partitioned.tests;
+    let base_name = |test: &str| test.rsplit('/').next().unwrap().to_owned();
+
+    for test in tests {
+        let name = base_name(test);
+        let name = if tests.iter().filter(|t| base_name(t) == name).count() > 1 {
+            escape_folder(test)
+        } else {
+            name
+        };
+
+        writeln!(
+            makefile,
+            ".PHONY: test-{name}\n\
+            test-{name}: {target}\n\
+            \t./{test} $(ARGS)\n",
+            name = name,
+            target = escape_folder(test),
+            test = test
+        )?;
+    }
+
+    Ok(())
+}
+
+// The stamp records the compiler's path and is rewritten when a different compiler is used
+// or the compiler itself is newer than it, which makes every object file out of date
+fn generate_compiler_stamp(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.compiler_dep {
+        return Ok(());
+    }
+
+    writeln!(
+        makefile,
+        "CC_PATH := $(shell command -v $(CC))\n\
+        COMPILER_STAMP := $(ODIR)/compiler.stamp\n\n\
+        $(COMPILER_STAMP): $(CC_PATH) FORCE | $(ODIR)\n\
+            \t@[ \"$$(cat $@ 2>/dev/null)\" = \"$(CC_PATH)\" ] && [ $@ -nt \"$(CC_PATH)\" ] || echo \"$(CC_PATH)\" > $@\n\n\
+        FORCE:\n"
+    )
+}
+
+// Switching the mode links other objects, which may well be older than the binaries, so the binaries
+// depend on a stamp that is rewritten whenever the mode differs from the one of the last build
+fn generate_mode_stamp(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    let mode = match mode_variable(ctx) {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+
+    writeln!(
+        makefile,
+        "MODE_STAMP := $(ODIR)/mode.stamp\n\n\
+        $(MODE_STAMP): FORCE | $(ODIR)\n\
+            \t@[ \"$$(cat $@ 2>/dev/null)\" = \"$({mode})\" ] || echo \"$({mode})\" > $@\n",
+        mode = mode
+    )?;
+
+    // The compiler stamp declares it already
+    if !ctx.cli.compiler_dep {
+        writeln!(makefile, "FORCE:\n")?;
+    }
+
+    Ok(())
+}
+
+// The variable selecting the build mode (and the directory of the objects), if any
+fn mode_variable(ctx: &GenerateContext) -> Option<&'static str> {
+    if ctx.cli.build_profiles {
+        Some("BUILD")
+    } else if ctx.cli.debug_switch {
+        Some("MODE")
+    } else {
+        None
+    }
+}
+
+fn mode_stamp(ctx: &GenerateContext) -> &'static str {
+    match mode_variable(ctx) {
+        Some(_) => " $(MODE_STAMP)",
+        None => "",
+    }
+}
+
+// The rules producing the generated files. The files depending on them list them in their
+// source dependencies, so they are generated before any of those is compiled, even under -j
+fn generate_generated_files(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    for (file, command) in &ctx.cli.generated {
+        writeln!(
+            makefile,
+            "{file}:{extra}\n\
+                \t{command}\n",
+            file = source_path(ctx, file),
+            extra = extra_prerequisites(ctx),
+            command = command
+        )?;
+    }
+
+    Ok(())
+}
+
+// Builds every object file without linking anything, for quickly checking that everything compiles
+fn generate_objects_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    write!(makefile, ".PHONY: objects\nobjects:")?;
+
+    for source in ctx.dep_map.keys() {
+        write!(makefile, " {}", object_file(ctx, &unit_name(source)))?;
+    }
+
+    writeln!(makefile, "\n")
+}
+
+// The fuzz target is built directly from the sources so that they are all instrumented,
+// and it's not part of `all`
+fn generate_fuzz_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    let fuzz_file = match ctx.cli.fuzz {
+        Some(fuzz_file) => fuzz_file.trim_start_matches("./"),
+        None => return Ok(()),
+    };
+
+    let dependencies = match ctx.dep_map.get(fuzz_file) {
+        Some((dependencies, _)) => dependencies,
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Could not find the fuzz target's source {}", fuzz_file),
+            ))
+        }
+    };
+
+    let sources = dependencies
+        .iter()
+        .filter(|d| ctx.dep_map.contains_key(*d))
+        .map(|d| source_path(ctx, d))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    writeln!(
+        makefile,
+        "fuzz: {prerequisites}{extra}\n\
+            \t$(CC) $(CFLAGS) -fsanitize=fuzzer,address {sources} -o fuzz $(LFLAGS)\n",
+        prerequisites = match ctx.cli.depmode {
+            "compiler" => sources.clone(),
+            _ => format!(
+                "$({})",
+                source_file_dependencies_var_name(flat_name(ctx, &unit_name(fuzz_file)))
+            ),
+        },
+        extra = extra_prerequisites(ctx),
+        sources = sources
+    )
+}
+
+fn generate_objcopy_targets(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if ctx.cli.objcopy.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(makefile, "OBJCOPY := {}objcopy\n", ctx.cli.toolchain_prefix)?;
+
+    let (target, out) = main_binary(ctx);
+    for format in &ctx.cli.objcopy {
+        writeln!(
+            makefile,
+            "{output}: {target}{suffix}\n\
+                \t$(OBJCOPY) -O {format} {binary}{suffix} {output}\n",
+            output = objcopy_output(ctx, format, binary_suffix(ctx)),
+            target = target,
+            binary = out,
+            suffix = binary_suffix(ctx),
+            format = format
+        )?;
+    }
+
+    Ok(())
+}
+
+// The archive holds the objects of the library code only, so that linking it
+// into another program doesn't bring in a second main function
+fn generate_static_lib_target(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    let name = match ctx.cli.static_lib {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+
+    let objects = library_objects(ctx);
+
+    writeln!(
+        makefile,
+        "AR := {prefix}{ar}\n\
+        LIBRARY_OBJECTS := {objects}\n\n\
+        {lib}: $(LIBRARY_OBJECTS){mode_stamp}\n\
+            \t$(AR) rcs {lib} $(LIBRARY_OBJECTS)\n",
+        prefix = ctx.cli.toolchain_prefix,
+        // Archives of LTO objects need the linker plugin's symbol index
+        ar = match ctx.cli.lto {
+            true if ctx.cli.compiler.contains("clang") => "llvm-ar",
+            true => "gcc-ar",
+            false => "ar",
+        },
+        objects = objects.join(" "),
+        mode_stamp = mode_stamp(ctx),
+        lib = static_lib(name)
+    )
+}
+
+// Like the archive, the shared library holds the objects of the library code only
+fn generate_shared_lib_target(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if ctx.cli.lib != Some("shared") {
+        return Ok(());
+    }
+
+    // The archive defines the same variable already
+    if ctx.cli.static_lib.is_none() {
+        writeln!(
+            makefile,
+            "LIBRARY_OBJECTS := {}",
+            library_objects(ctx).join(" ")
+        )?;
+    }
+
+    writeln!(
+        makefile,
+        "\n{lib}: $(LIBRARY_OBJECTS){mode_stamp}\n\
+            \t{link} -shared {link_cflags} $(LIBRARY_OBJECTS) -o {lib} $(LFLAGS)\n",
+        link = link_command(ctx),
+        link_cflags = link_cflags(ctx),
+        mode_stamp = mode_stamp(ctx),
+        lib = shared_lib(ctx.cli.binary)
+    )
+}
+
+fn library_objects(ctx: &GenerateContext) -> Vec<String> {
+    ctx.dep_map
+        .keys()
+        .filter(|source| is_library_source(ctx, source))
+        .map(|source| object_file(ctx, &unit_name(source)))
+        .collect()
+}
+
+fn shared_lib(name: &str) -> String {
+    format!("lib{}.so", name)
+}
+
+// The libraries the project is built into
+fn libraries(ctx: &GenerateContext) -> Vec<String> {
+    ctx.cli
+        .static_lib
+        .map(static_lib)
+        .into_iter()
+        .chain(
+            ctx.cli
+                .lib
+                .filter(|kind| *kind == "shared")
+                .map(|_| shared_lib(ctx.cli.binary)),
+        )
+        .collect()
+}
+
+// Whether the objects of the source go into the library archive
+fn is_library_source(ctx: &GenerateContext, source: &str) -> bool {
+    let file = strip_extension(source);
+    !ctx.dep_map[source].1
+        && !contained_in_partition(&ctx.cli.tests, file)
+        && !contained_in_partition(&ctx.cli.benchmarks, file)
+        && !contained_in_partition(&ctx.cli.examples, file)
+}
+
+fn static_lib(name: &str) -> String {
+    format!("lib{}.a", name)
+}
+
+// The file objcopy converts the main binary to for the given format
+// The image of the main program in the given format, named after the program with the given binary suffix
+fn objcopy_output(ctx: &GenerateContext, format: &str, suffix: &str) -> String {
+    let extension = match format {
+        "binary" => "bin",
+        "ihex" => "hex",
+        format => format,
+    };
+
+    format!("{}{}.{}", main_binary(ctx).1, suffix, extension)
+}
+
+// The target and the output file of the main program
+fn main_binary<'a>(ctx: &'a GenerateContext) -> (String, &'a str) {
+    standalone_binary(ctx.cli, ctx.partitioned, &ctx.partitioned.main_file)
+}
+
+fn generate_lint_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.lint_target {
+        return Ok(());
+    }
+
+    writeln!(
+        makefile,
+        "LINTER := {linter}\n\n\
+        .PHONY: lint\n\
+        lint:\n\
+            \t@for source in $(SOURCES); do $(LINTER) $$source || exit 1; done\n",
+        linter = ctx.cli.linter
+    )
+}
+
+// clang-tidy gets the flags the sources are compiled with after the --
+fn generate_tidy_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.tidy_target {
+        return Ok(());
+    }
+
+    let recipe = if ctx.cli.mixed {
+        "\t$(CLANG_TIDY) $(filter %.c,$(SOURCES)) -- $(CFLAGS)\n\
+        \t$(CLANG_TIDY) $(filter-out %.c,$(SOURCES)) -- $(CXXFLAGS)"
+    } else {
+        "\t$(CLANG_TIDY) $(SOURCES) -- $(CFLAGS)"
+    };
+
+    writeln!(
+        makefile,
+        "CLANG_TIDY := clang-tidy\n\n\
+        .PHONY: tidy\n\
+        tidy:\n\
+        {recipe}\n",
+        recipe = recipe
+    )
+}
+
+fn generate_format_targets(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.format_targets {
+        return Ok(());
+    }
+
+    writeln!(
+        makefile,
+        "CLANG_FORMAT := clang-format\n\n\
+        .PHONY: format format-check\n\
+        format:\n\
+            \t$(CLANG_FORMAT) -i $(FORMAT_FILES)\n\n\
+        format-check:\n\
+            \t$(CLANG_FORMAT) --dry-run --Werror $(FORMAT_FILES)\n"
+    )
+}
+
+fn generate_tags_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.tags_target {
+        return Ok(());
+    }
+
+    let roots = if ctx.cli.source_roots.is_empty() {
+        source_path(ctx, ".")
+    } else {
+        ctx.cli
+            .source_roots
+            .iter()
+            .map(|root| source_path(ctx, root))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    writeln!(
+        makefile,
+        "CTAGS := {ctags}\n\n\
+        .PHONY: tags\n\
+        tags:\n\
+            \t$(CTAGS) -R {roots}\n",
+        ctags = ctx.cli.ctags,
+        roots = roots
+    )
+}
+
+// Rebuilds every object under bear, so the database holds the commands that really ran
+fn generate_compdb_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.emit_bear_db {
+        return Ok(());
+    }
+
+    writeln!(
+        makefile,
+        "BEAR := bear\n\n\
+        .PHONY: compdb\n\
+        compdb:\n\
+            \t$(BEAR) --output compile_commands.json -- $(MAKE) -B objects\n"
+    )
+}
+
+fn generate_dist_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.dist {
+        return Ok(());
+    }
+
+    let archive_command = match ctx.cli.archive_format {
+        "zip" => "zip -q",
+        "tar.bz2" => "tar -cjf",
+        "tar.xz" => "tar -cJf",
+        _ => "tar -czf",
+    };
+
+    writeln!(
+        makefile,
+        "VERSION ?= {version}\n\
+        DIST_ARCHIVE := {archive}\n\n\
+        .PHONY: dist\n\
+        dist: $(DIST_ARCHIVE)\n\n\
+        $(DIST_ARCHIVE): $(DIST_FILES)\n\
+            \t{archive_command} $(DIST_ARCHIVE) $(DIST_FILES)\n",
+        version = ctx.cli.project_version,
+        archive = dist_archive(ctx),
+        archive_command = archive_command
+    )
+}
+
+fn dist_archive(ctx: &GenerateContext) -> String {
+    format!("{}-$(VERSION).{}", ctx.cli.binary, ctx.cli.archive_format)
+}
+
+// The sanitizer targets and their flags
+const SANITIZERS: &[(&str, &str)] = &[
+    ("asan", "-fsanitize=address -fno-omit-frame-pointer"),
+    ("ubsan", "-fsanitize=undefined"),
+    ("tsan", "-fsanitize=thread"),
+];
+
+// Each sanitizer builds the project again in its own object directory, since its objects can't be mixed with the others.
+// The binaries are suffixed with its name as well (e.g. foo-asan), so the ones of the regular build are left alone
+fn generate_sanitizer_targets(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if !ctx.cli.sanitizers {
+        return Ok(());
+    }
+
+    for (target, flags) in SANITIZERS {
+        writeln!(
+            makefile,
+            ".PHONY: {target}\n\
+            {target}:\n\
+            \t@mkdir -p $(ODIR)/{target}\n\
+            \t$(MAKE) ODIR=$(ODIR)/{target} BIN_SUFFIX=-{target} SANITIZE_FLAGS=\"-g {flags}\"\n",
+            target = target,
+            flags = flags
+        )?;
+    }
+
+    Ok(())
+}
+
+fn generate_dep_graph_target(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    if !ctx.cli.dep_graph_target {
+        return Ok(());
+    }
+
+    // Regenerate the graph with the exact same arguments this Makefile was generated with
+    let args = ctx
+        .cli
+        .invocation
+        .iter()
+        .map(|arg| shell_quote(arg).replace('$', "$$"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    writeln!(
+        makefile,
+        ".PHONY: dep-graph\n\
+        dep-graph:\n\
+            \tmakegen {args} --emit-dot\n\
+            \tdot -Tpng {dot} -o deps.png\n",
+        args = args,
+        dot = DOT_FILE
+    )
+}
+
+// Installs with the PREFIX and DESTDIR conventions, e.g. make install PREFIX=/usr DESTDIR=pkg
+fn generate_install_targets(
+    makefile: &mut dyn Write,
+    ctx: &GenerateContext,
+) -> std::io::Result<()> {
+    let artifacts = match &ctx.cli.install {
+        Some(artifacts) if artifacts.is_empty() && !libraries(ctx).is_empty() => {
+            vec!["binaries", "library", "headers"]
+        }
+        Some(artifacts) if artifacts.is_empty() => vec!["binaries"],
+        Some(artifacts) => artifacts.clone(),
+        None => return Ok(()),
+    };
+
+    // (the file, where it's installed relative to the prefix, its mode)
+    let mut files = Vec::new();
+    let mut prerequisites = Vec::new();
+
+    if artifacts.contains(&"binaries") {
+        prerequisites.push("binaries".to_owned());
+        for bin_file in &ctx.partitioned.standalone {
+            let (_, out) = standalone_binary(ctx.cli, ctx.partitioned, bin_file);
+            let name = Path::new(&out).file_name().unwrap().to_str().unwrap();
+            files.push((out.to_owned(), format!("bin/{}", name), "755"));
+        }
+    }
+
+    if artifacts.contains(&"library") {
+        let libraries = libraries(ctx);
+        if libraries.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Installing the library requires --static-lib or --lib",
+            ));
+        }
+        for lib in libraries {
+            prerequisites.push(lib.clone());
+            // The shared library is mapped into memory to run, the archive is only read
+            let mode = if lib.ends_with(".so") { "755" } else { "644" };
+            files.push((lib.clone(), format!("lib/{}", lib), mode));
+        }
+    }
+
+    // Headers are installed relative to the directory they are included from
+    if artifacts.contains(&"headers") {
+        let search_dirs = ctx.cli.quote_search_dirs();
+        for header in parsed_files(ctx).filter(|f| {
+            HEADER_EXTENSIONS.iter().any(|ext| has_extension(f, ext))
+                && !ctx.cli.generated.iter().any(|(output, _)| output == f)
+                && !contained_in_partition(&ctx.cli.tests, f)
+                && !contained_in_partition(&ctx.cli.benchmarks, f)
+                && !contained_in_partition(&ctx.cli.examples, f)
+        }) {
+            let relative = search_dirs
+                .iter()
+                .find_map(|dir| {
+                    Path::new(header)
+                        .strip_prefix(dir.trim_start_matches("./"))
+                        .ok()
+                })
+                .unwrap_or_else(|| Path::new(header));
+            files.push((
+                source_path(ctx, header),
+                format!("include/{}", relative.display()),
+                "644",
+            ));
+        }
+    }
+
+    writeln!(
+        makefile,
+        "PREFIX ?= /usr/local\n\n\
+        .PHONY: install uninstall\n\
+        install: {}",
+        prerequisites.join(" ")
+    )?;
+    for (file, destination, mode) in &files {
+        writeln!(
+            makefile,
+            "\tinstall -D -m {} {} $(DESTDIR)$(PREFIX)/{}",
+            mode, file, destination
+        )?;
+    }
+
+    writeln!(makefile, "\nuninstall:")?;
+    for (_, destination, _) in &files {
+        writeln!(makefile, "\trm -f $(DESTDIR)$(PREFIX)/{}", destination)?;
+    }
+    writeln!(makefile)
+}
+
+fn generate_clean_target(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    write!(
+        makefile,
+        ".PHONY: clean\n\
+        clean:\n\
+            \trm -rf .OBJ ",
+    )?;
+
+    let all_files = ctx
+        .partitioned
+        .standalone
+        .iter()
+        .map(|f| standalone_binary(ctx.cli, ctx.partitioned, f).1)
+        .chain(ctx.partitioned.tests.iter().copied())
+        .chain(ctx.partitioned.benchmarks.iter().copied())
+        .chain(ctx.partitioned.examples.iter().copied());
+
+    for file in all_files {
+        write!(makefile, "{} ", file)?;
+    }
+
+    if ctx.cli.sanitizers {
+        for file in &ctx.partitioned.standalone {
+            let (_, out) = standalone_binary(ctx.cli, ctx.partitioned, file);
+            for (sanitizer, _) in SANITIZERS {
+                write!(makefile, "{}-{} ", out, sanitizer)?;
+            }
+        }
+    }
+
+    for format in &ctx.cli.objcopy {
+        write!(makefile, "{} ", objcopy_output(ctx, format, ""))?;
+        if ctx.cli.sanitizers {
+            for (sanitizer, _) in SANITIZERS {
+                let suffix = format!("-{}", sanitizer);
+                write!(makefile, "{} ", objcopy_output(ctx, format, &suffix))?;
+            }
+        }
+    }
+
+    for lib in libraries(ctx) {
+        write!(makefile, "{} ", lib)?;
+    }
+
+    if ctx.cli.fuzz.is_some() {
+        write!(makefile, "fuzz ")?;
+    }
+
+    for (file, _) in &ctx.cli.generated {
+        write!(makefile, "{} ", source_path(ctx, file))?;
+    }
+
+    if ctx.cli.tags_target {
+        write!(makefile, "tags ")?;
+    }
+
+    if ctx.cli.dist {
+        write!(makefile, "$(DIST_ARCHIVE) ")?;
+    }
+
+    if ctx.cli.depfile_dir.is_some() {
+        write!(makefile, "$(DEPDIR) ")?;
+    }
+
+    if ctx.cli.dep_graph_target {
+        write!(makefile, "{} deps.png ", DOT_FILE)?;
+    }
+
+    // GCC stores the compiled module interfaces there
+    if ctx.cli.modules {
+        write!(makefile, "gcm.cache ")?;
+    }
+
+    writeln!(makefile)?;
+
+    Ok(())
+}
+
+// The flags making the compiler write the dependencies of the object file it builds.
+// By default the depfile is placed next to the object file
+fn depfile_flags(ctx: &GenerateContext, file: &str) -> String {
+    match ctx.cli.depfile_dir {
+        _ if !ctx.cli.auto_deps => String::new(),
+        Some(_) => format!(" -MMD -MP -MF {}", depfile(ctx, file)),
+        None => " -MMD -MP".to_owned(),
+    }
+}
+
+// The depfile written in the depfile directory for the given file (without its extension)
+fn depfile(ctx: &GenerateContext, file: &str) -> String {
+    format!("$(DEPDIR)/{}.d", object_path(ctx, file))
+}
+
+// With --compact the sources are compiled by a pattern rule per extension instead of a rule each,
+// except for the ones that need a recipe of their own
+fn is_pattern_compiled(ctx: &GenerateContext, source: &str) -> bool {
+    ctx.cli.compact
+        && !is_module_interface(source)
+        && !is_extensionless(source)
+        && object_recipe(ctx, source).is_none()
+        && !(ctx.cli.test_framework.is_some()
+            && contained_in_partition(&ctx.cli.tests, &unit_name(source)))
+}
+
+fn generate_pattern_rules(makefile: &mut dyn Write, ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.compact {
+        return Ok(());
+    }
+
+    writeln!(makefile)?;
+
+    let depfile = "$(DEPDIR)/$*.d";
+    for ext in source_extensions(ctx.cli.source_extension()) {
+        let used = ctx
+            .dep_map
+            .keys()
+            .any(|source| has_extension(source, ext) && is_pattern_compiled(ctx, source));
+        if !used {
+            continue;
+        }
+
+        let (compile, flags) = if ctx.cli.mixed && ext != "c" {
+            ("$(CXX)", "$(CXXFLAGS)")
+        } else {
+            ("$(CC)", "$(CFLAGS)")
+        };
+
+        writeln!(
+            makefile,
+            "{dir}/%{suffix}: {source}{extra}{compiler} | $(ODIR){depdir}\n\
+                {mkdir}{echo}\t{compile} -c {flags}{depfile_flags} $< -o $@\n",
+            dir = object_dir(ctx),
+            suffix = ctx.cli.obj_suffix,
+            source = source_path(ctx, &format!("%.{}", ext)),
+            extra = extra_prerequisites(ctx),
+            compiler = if ctx.cli.compiler_dep {
+                " $(COMPILER_STAMP)"
+            } else {
+                ""
+            },
+            depdir = if ctx.cli.depfile_dir.is_some() {
+                " $(DEPDIR)"
+            } else {
+                ""
+            },
+            mkdir = match ctx.cli.depfile_dir {
+                Some(_) if ctx.cli.auto_deps => format!("\t@mkdir -p $(@D) $(dir {})\n", depfile),
+                _ => "\t@mkdir -p $(@D)\n".to_owned(),
+            },
+            echo = if ctx.cli.echo_includes {
+                "\t@echo \"$@: $^\" >&2\n"
+            } else {
+                ""
+            },
+            compile = compile,
+            flags = flags,
+            depfile_flags = match ctx.cli.depfile_dir {
+                _ if !ctx.cli.auto_deps => String::new(),
+                Some(_) => format!(" -MMD -MP -MF {}", depfile),
+                None => " -MMD -MP".to_owned(),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+// The binaries are linked by the compiler unless a separate linker is given
+fn link_command(ctx: &GenerateContext) -> &'static str {
+    if ctx.cli.linker.is_some() {
+        "$(LINKER)"
+    } else if ctx.cli.mixed {
+        "$(CXX)"
+    } else {
+        "$(CC)"
+    }
+}
+
+// The C compiler of the same family as the C++ one, e.g. clang for clang++ and gcc-12 for g++-12
+fn c_compiler(compiler: &str) -> String {
+    const FAMILIES: [(&str, &str); 3] = [("clang++", "clang"), ("g++", "gcc"), ("c++", "cc")];
+
+    FAMILIES
+        .iter()
+        .find(|(cxx, _)| compiler.contains(cxx))
+        .map(|(cxx, cc)| compiler.replacen(cxx, cc, 1))
+        .unwrap_or_else(|| "cc".to_owned())
+}
+
+// Mixed projects are linked as C++, so the C standard isn't passed to the linker
+fn link_cflags(ctx: &GenerateContext) -> &'static str {
+    if ctx.cli.mixed {
+        "$(CXXFLAGS)"
+    } else {
+        "$(CFLAGS)"
+    }
+}
+
+// The target and the output file of the binary built from the given standalone file (without its extension).
+// Files mapped with --program and the main file are named after their program, the rest after themselves
+fn standalone_binary<'a>(
+    cli: &'a Cli,
+    partitioned: &PartitionedFiles,
+    file: &'a str,
+) -> (String, &'a str) {
+    let program = cli
+        .programs
+        .iter()
+        .find(|(_, f)| strip_extension(&normalize_path(f.trim_start_matches("./"))) == file);
+
+    match program {
+        Some((name, _)) => (escape_folder(name), name),
+        None if file == partitioned.main_file => (escape_folder(cli.binary), cli.binary),
+        None => (format!("bin_{}", escape_folder(file)), file),
+    }
+}
+
+// Linking two mains into the same program would fail, so report it instead
+fn ensure_unique_programs(ctx: &GenerateContext) -> std::io::Result<()> {
+    let standalone = &ctx.partitioned.standalone;
+
+    for (i, file) in standalone.iter().enumerate() {
+        let (_, out) = standalone_binary(ctx.cli, ctx.partitioned, file);
+        let conflict = standalone[i + 1..]
+            .iter()
+            .find(|other| standalone_binary(ctx.cli, ctx.partitioned, other).1 == out);
+
+        if let Some(other) = conflict {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Both {} and {} define a main function and are built as the program {}",
+                    binary_source(ctx.dep_map, file, ctx.cli.source_extension()),
+                    binary_source(ctx.dep_map, other, ctx.cli.source_extension()),
+                    out
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// A source without an extension would be overwritten by the binary named after it
+fn ensure_sources_not_overwritten(ctx: &GenerateContext) -> std::io::Result<()> {
+    let outputs = ctx
+        .partitioned
+        .standalone
+        .iter()
+        .map(|f| standalone_binary(ctx.cli, ctx.partitioned, f).1)
+        .chain(ctx.partitioned.tests.iter().copied())
+        .chain(ctx.partitioned.benchmarks.iter().copied())
+        .chain(ctx.partitioned.examples.iter().copied());
+
+    for out in outputs {
+        if ctx.dep_map.contains_key(out) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The binary {out} would overwrite the source {out}, name it differently with --program NAME={out}",
+                    out = out
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Libraries can be given either by name or as a path to the archive
+fn library_flag(library: &str) -> String {
+    if library.contains('/') || library.ends_with(".a") {
+        library.to_owned()
+    } else {
+        format!("-l{}", library)
+    }
+}
+
+// The extra compiler and linker flags needed to build against a test framework
+fn test_framework_flags(framework: &str) -> (&'static str, &'static str) {
+    match framework {
+        "unity" => ("", "-lunity"),
+        "criterion" => ("", "-lcriterion"),
+        "catch2" => ("", "-lCatch2Main -lCatch2"),
+        "gtest" => ("-pthread", "-lgtest_main -lgtest -pthread"),
+        "cmocka" => ("", "-lcmocka"),
+        _ => ("", ""),
+    }
+}
+
+// Quotes an argument so that the shell passes it through unchanged
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_=+./,:@%".contains(c);
+
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// The rpath as a single quoted argument, with its $ escaped for make unless it already is
+fn rpath_flag(dir: &str) -> String {
+    let dir = dir.replace("$$", "$").replace('$', "$$");
+    format!("'-Wl,-rpath,{}'", dir.replace('\'', "'\\''"))
+}
+
+// The extra link flags given for a binary with --binary-lflags, matched against any of its names
+fn binary_lflags(ctx: &GenerateContext, names: &[&str]) -> String {
+    ctx.cli
+        .binary_lflags
+        .iter()
+        .filter(|(binary, _)| names.contains(binary))
+        .map(|(_, flags)| format!(" {}", flags))
+        .collect()
+}
+
+// Set by the sanitizer targets, so their binaries don't overwrite the regular ones
+fn binary_suffix(ctx: &GenerateContext) -> &'static str {
+    if ctx.cli.sanitizers {
+        "$(BIN_SUFFIX)"
+    } else {
+        ""
+    }
+}
+
+// Prerequisites shared by every object and binary rule
+fn extra_prerequisites(ctx: &GenerateContext) -> String {
+    let mut extra = String::new();
+
+    if ctx.cli.self_dep {
+        extra.push_str(" Makefile");
+    }
+
+    extra
+}
+
+// With --sort-by-deps the files with the fewest dependencies come first, otherwise they are sorted by name
+fn object_rule_order<'d>(ctx: &GenerateContext<'_, '_, 'd>) -> Vec<&'d String> {
+    let mut sources: Vec<_> = ctx.dep_map.keys().collect();
+
+    if ctx.cli.sort_by_deps {
+        sources.sort_by_key(|source| ctx.dep_map[*source].0.len());
+    }
+
+    sources
+}
+
+// Whether the file gets compiled to an object file
+fn is_translation_unit(cli: &Cli, file: &str) -> bool {
+    has_extension(file, cli.source_extension())
+        || (cli.modules && is_module_interface(file))
+        || (cli.detect_by_content && is_extensionless(file))
+}
+
+// Sources without an extension are only parsed with --detect-by-content
+fn is_extensionless(file: &str) -> bool {
+    Path::new(file).extension().is_none()
+}
+
+// The source file a binary is built from, given its name without the extension
+fn binary_source(dep_map: &DependencyMap, file: &str, extension: &str) -> String {
+    source_extensions(extension)
+        .iter()
+        .map(|ext| format!("{}.{}", file, ext))
+        .find(|source| dep_map.contains_key(source))
+        .unwrap_or_else(|| file.to_owned())
+}
+
+// The name a translation unit's object file and variables are derived from.
+// Module interfaces keep their extension so they don't clash with their implementation unit
+fn unit_name(file: &str) -> String {
+    if is_module_interface(file) {
+        file.replacen('.', "_", 1)
+    } else {
+        strip_extension(file).to_owned()
+    }
+}
+
+// The path of the object file of a source file (given without its extension)
+fn object_file(ctx: &GenerateContext, file: &str) -> String {
+    format!(
+        "{}/{}{}",
+        object_dir(ctx),
+        object_path(ctx, file),
+        ctx.cli.obj_suffix
+    )
+}
+
+// Each build profile (or mode) gets its own object directory so that switching between them doesn't clobber the objects
+fn object_dir(ctx: &GenerateContext) -> &'static str {
+    if ctx.cli.build_profiles {
+        "$(ODIR)/$(BUILD)"
+    } else if ctx.cli.debug_switch {
+        "$(ODIR)/$(MODE)"
+    } else {
+        "$(ODIR)"
+    }
+}
+
+// Creates the directories of a mirrored or per profile object file (and its depfile) before compiling it
+fn object_dirs(ctx: &GenerateContext, file: &str) -> String {
+    if !ctx.cli.mirror_dirs && !ctx.cli.build_profiles && !ctx.cli.debug_switch {
+        return String::new();
+    }
+
+    match ctx.cli.depfile_dir {
+        Some(_) if ctx.cli.auto_deps => {
+            format!("\t@mkdir -p $(@D) $(dir {})\n", depfile(ctx, file))
+        }
+        _ => "\t@mkdir -p $(@D)\n".to_owned(),
+    }
+}
+
+// Where the artifacts of a file go relative to their directory, either flattened or mirroring the sources
+fn object_path(ctx: &GenerateContext, file: &str) -> String {
+    if ctx.cli.flat {
+        flat_name(ctx, file).to_owned()
+    } else if ctx.cli.mirror_dirs {
+        normalize_path(file)
+    } else {
+        escape_folder(file)
+    }
+}
+
+// The name the objects and variables of a file are derived from, which --flat reduces to the base name
+fn flat_name<'a>(ctx: &GenerateContext, file: &'a str) -> &'a str {
+    if ctx.cli.flat {
+        file.rsplit(['/', '\\']).next().unwrap()
+    } else {
+        file
+    }
+}
+
+// Base names can only identify the files with --flat as long as no two of them share one
+fn ensure_unique_flat_names(ctx: &GenerateContext) -> std::io::Result<()> {
+    if !ctx.cli.flat {
+        return Ok(());
+    }
+
+    let mut names: HashMap<String, &str> = HashMap::new();
+    for source in ctx.dep_map.keys() {
+        let name = unit_name(flat_name(ctx, source));
+        if let Some(other) = names.insert(name.clone(), source) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Both {} and {} are named {}, which --flat can't tell apart",
+                    other, source, name
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Two rules writing the same object would race under make -j, e.g. for a/b_c.c and a_b/c.c,
+// or for util.c and util.cpp in mixed projects
+fn ensure_unique_objects(ctx: &GenerateContext) -> std::io::Result<()> {
+    let mut objects: HashMap<String, &str> = HashMap::new();
+    for source in ctx.dep_map.keys() {
+        let object = object_file(ctx, &unit_name(source));
+        if let Some(other) = objects.insert(object.clone(), source) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Both {} and {} are compiled to {}", other, source, object),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// The recipe given with --object-recipe for the source file, if any
+fn object_recipe<'c>(ctx: &GenerateContext<'c, '_, '_>, source: &str) -> Option<&'c str> {
+    ctx.cli
+        .object_recipes
+        .iter()
+        .find(|(file, _)| file.trim_start_matches("./") == source)
+        .map(|(_, recipe)| *recipe)
+}
+
+// A recipe for a file that isn't compiled would silently go unused
+fn ensure_object_recipes_known(ctx: &GenerateContext) -> std::io::Result<()> {
+    for (file, _) in &ctx.cli.object_recipes {
+        let source = file.trim_start_matches("./");
+        if !ctx.dep_map.contains_key(source) || !is_translation_unit(ctx.cli, source) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} given with --object-recipe is not one of the source files",
+                    file
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_test_runner(cli: &Cli, file: &str) -> bool {
+    cli.test_runner
+        .map(|runner| {
+            strip_extension(&normalize_path(runner.trim_start_matches("./")))
+                == strip_extension(file)
+        })
+        .unwrap_or(false)
+}
+
+// The test files without a main function, which are linked into the --test-runner instead of being built on their own
+fn runner_test_files<'d>(ctx: &GenerateContext<'_, '_, 'd>) -> Vec<&'d String> {
+    ctx.dep_map
+        .iter()
+        .filter(|(file, (_, has_main))| {
+            !has_main
+                && is_translation_unit(ctx.cli, file)
+                && contained_in_partition(&ctx.cli.tests, strip_extension(file))
+        })
+        .map(|(file, _)| file)
+        .collect()
+}
+
+// The test files are linked into the runner, so it must be one of the programs
+fn ensure_test_runner_has_main(ctx: &GenerateContext) -> std::io::Result<()> {
+    let runner = match ctx.cli.test_runner {
+        Some(runner) => runner,
+        None => return Ok(()),
+    };
+
+    let has_main = ctx
+        .dep_map
+        .iter()
+        .any(|(file, (_, has_main))| *has_main && is_test_runner(ctx.cli, file));
+    if !has_main {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "{} given with --test-runner is not a file with a main function",
+                runner
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// The path of a file of the project as referenced from the Makefile.
+// Relocatable Makefiles anchor them to the Makefile's directory instead of the working directory
+fn source_path(ctx: &GenerateContext, file: &str) -> String {
+    if ctx.cli.relocatable {
+        format!("$(SRCDIR){}", file)
+    } else {
+        file.to_owned()
+    }
+}
+
+#[inline]
+fn escape_folder(filename: &str) -> String {
+    filename.replace(['/', '\\'], "_")
+}
+
+#[inline]
+fn file_dependencies_var_name(filename: &str, category: &str) -> String {
+    let var_name = escape_folder(filename);
+    format!("{}_{}_DEPS", var_name.to_ascii_uppercase(), category)
+}
+
+#[inline]
+fn source_file_dependencies_var_name(filename: &str) -> String {
+    file_dependencies_var_name(filename, "SOURCE")
+}
+
+#[inline]
+fn object_file_dependencies_var_name(filename: &str) -> String {
+    file_dependencies_var_name(filename, "OBJECT")
+}
+
+// What an object is rebuilt on before any depfile exists: either makegen's dependencies, or just the source
+// (and the generated files it includes, which have to be made before it's compiled) for the compiler's
+fn object_sources(ctx: &GenerateContext, source: &str, file: &str) -> String {
+    if ctx.cli.depmode != "compiler" {
+        return format!(
+            "$({})",
+            source_file_dependencies_var_name(flat_name(ctx, file))
+        );
+    }
+
+    std::iter::once(source)
+        .chain(
+            ctx.dep_map[source]
+                .0
+                .iter()
+                .map(String::as_str)
+                .filter(|d| {
+                    ctx.cli
+                        .generated
+                        .iter()
+                        .any(|(output, _)| output.trim_start_matches("./") == *d)
+                }),
+        )
+        .map(|f| source_path(ctx, f))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli, parser::Parser};
+    use std::{fs, io::Write, path::Path};
+
+    fn dependency_map(entries: &[(&str, &[&str], bool)]) -> DependencyMap {
+        entries
+            .iter()
+            .map(|(file, deps, has_main)| {
+                let deps = deps.iter().map(|d| d.to_string()).collect();
+                (file.to_string(), (deps, *has_main))
+            })
+            .collect()
+    }
+
+    fn generate(args: &[&str], dependency_map: DependencyMap) -> String {
+        let matches = cli::app().get_matches_from(["makegen"].iter().chain(args));
+        let mut cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        cli.invocation = args.iter().map(|arg| arg.to_string()).collect();
+        let mut makefile = Vec::new();
+        let parse_result = ParseResult::new(dependency_map, Vec::new());
+        write_makefile(&mut makefile, &cli, &parse_result).unwrap();
+        String::from_utf8(makefile).unwrap()
+    }
+
+    #[test]
+    fn packages_file_generates_pkg_config_calls() {
+        let mut packages = tempfile::NamedTempFile::new().unwrap();
+        writeln!(packages, "# dependencies\ngtk+-3.0\n\nlibpng").unwrap();
+        let packages_file = packages.path().to_str().unwrap();
+
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--packages-file", packages_file],
+            dep_map,
+        );
+
+        for package in &["gtk+-3.0", "libpng"] {
+            assert!(makefile.contains(&format!(
+                "CFLAGS += $(shell pkg-config --cflags {})",
+                package
+            )));
+            assert!(makefile.contains(&format!("LFLAGS += $(shell pkg-config --libs {})", package)));
+        }
+    }
+
+    #[test]
+    fn self_dep_adds_makefile_prerequisite() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h", "util.c"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--self-dep"], dep_map);
+
+        assert!(makefile.contains("$(ODIR)/util.o: $(UTIL_SOURCE_DEPS) Makefile | $(ODIR)\n"));
+        assert!(makefile.contains("$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) Makefile | $(ODIR)\n"));
+        assert!(makefile.contains("foo: $(MAIN_OBJECT_DEPS) Makefile | $(ODIR)\n"));
+        assert!(!makefile.contains("Makefile:"));
+    }
+
+    #[test]
+    fn test_framework_flags_only_apply_to_tests() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/test_foo.c", &["tests/test_foo.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--test-framework", "criterion"],
+            dep_map,
+        );
+
+        assert!(makefile.contains("TEST_LFLAGS := -lcriterion\n"));
+        assert!(makefile.contains(
+            "\t$(CC) $(CFLAGS) $(TEST_CFLAGS) $(TESTS_TEST_FOO_OBJECT_DEPS) -o tests/test_foo $(TEST_LFLAGS)\n"
+        ));
+        assert!(makefile.contains("\t$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS)\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) $(TEST_CFLAGS) tests/test_foo.c"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) main.c"));
+    }
+
+    #[test]
+    fn obj_suffix_is_used_for_all_objects() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h", "util.c"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--obj-suffix", ".obj"], dep_map);
+
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.obj $(ODIR)/util.obj\n"));
+        assert!(makefile.contains("$(ODIR)/util.obj: $(UTIL_SOURCE_DEPS) | $(ODIR)\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) util.c -o $(ODIR)/util.obj\n"));
+        assert!(!makefile.contains(".o "));
+        assert!(!makefile.contains(".o\n"));
+    }
+
+    #[test]
+    fn test_keep_going_runs_every_test() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/test_foo.c", &["tests/test_foo.c"], true),
+        ]);
+
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map.clone());
+        assert!(makefile.contains(
+            ".PHONY: check run-tests\n\
+            check run-tests: tests\n\
+            \t@passed=0; failed=0; \\\n\
+            \tfor test in tests/test_foo; do \\\n\
+            \t\tif ./$$test; then \\\n\
+            \t\t\techo \"PASS: $$test\"; passed=$$((passed + 1)); \\\n\
+            \t\telse \\\n\
+            \t\t\techo \"FAIL: $$test\"; failed=$$((failed + 1)); \\\n\
+            \t\t\t[ -n \"$(findstring k,$(firstword -$(MAKEFLAGS)))\" ] || break; \\\n\
+            \t\tfi; \\\n\
+            \tdone; \\\n\
+            \techo \"$$passed passed, $$failed failed\"; \\\n\
+            \t[ $$failed -eq 0 ]\n"
+        ));
+
+        let makefile = generate(&["-b", "foo", "-e", "c", "--test-keep-going"], dep_map);
+        assert!(makefile.contains("\tfor test in tests/test_foo; do \\\n"));
+        assert!(makefile.contains("failed=$$((failed + 1)); \\\n\t\tfi; \\\n"));
+        assert!(!makefile.contains("|| break"));
+    }
+
+    #[test]
+    fn whole_archive_wraps_library() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--link-whole-archive",
+                "plugins",
+                "--link-whole-archive",
+                "lib/libtests.a",
+            ],
+            dep_map,
+        );
+
+        assert!(
+            makefile.contains("LFLAGS += -Wl,--whole-archive -lplugins -Wl,--no-whole-archive\n")
+        );
+        assert!(makefile
+            .contains("LFLAGS += -Wl,--whole-archive lib/libtests.a -Wl,--no-whole-archive\n"));
+    }
+
+    #[test]
+    fn link_group_surrounds_libraries() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--link-group", "a lib/libb.a c"],
+            dep_map,
+        );
+
+        assert!(
+            makefile.contains("LFLAGS += -Wl,--start-group -la lib/libb.a -lc -Wl,--end-group\n")
+        );
+    }
+
+    #[test]
+    fn dep_graph_target_regenerates_and_renders_dot() {
+        let dep_map = dependency_map(&[("main.c", &["util.h"], true), ("util.h", &[], false)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--test-framework",
+                "gtest",
+                "--dep-graph-target",
+            ],
+            dep_map.clone(),
+        );
+
+        assert!(makefile.contains(
+            "dep-graph:\n\
+            \tmakegen -b foo -e c --test-framework gtest --dep-graph-target --emit-dot\n\
+            \tdot -Tpng deps.dot -o deps.png\n"
+        ));
+
+        let mut dot = Vec::new();
+        write_dot(&mut dot, &dep_map).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph dependencies {\n    \"main.c\" -> \"util.h\";\n    \"util.h\";\n}\n"
+        );
+    }
+
+    #[test]
+    fn shell_quote_works() {
+        assert_eq!(shell_quote("--binary=foo"), "--binary=foo");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn modules_build_interface_before_consumer() {
+        let root_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            root_dir.path().join("math.cppm"),
+            "export module math;\nexport int add(int a, int b) { return a + b; }\n",
+        )
+        .unwrap();
+        fs::write(
+            root_dir.path().join("main.cpp"),
+            "import math;\nint main() { return add(1, 2); }\n",
+        )
+        .unwrap();
+
+        let matches =
+            cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "cpp", "--modules"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let parse_result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        let mut makefile = Vec::new();
+        write_makefile(&mut makefile, &cli, &parse_result).unwrap();
+        let makefile = String::from_utf8(makefile).unwrap();
+
+        assert!(makefile.contains("CFLAGS += -fmodules-ts\n"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/math_cppm.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/math_cppm.o: $(MATH_CPPM_SOURCE_DEPS) | $(ODIR)\n\
+            \t$(CC) -c $(CFLAGS) -x c++ math.cppm -o $(ODIR)/math_cppm.o\n"
+        ));
+        assert!(makefile
+            .contains("$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) $(ODIR)/math_cppm.o | $(ODIR)\n"));
+    }
+
+    #[test]
+    fn binary_lflags_only_apply_to_named_binary() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tools/daemon.c", &["tools/daemon.c"], true),
+            ("tests/test_foo.c", &["tests/test_foo.c"], true),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--binary-lflags",
+                "foo=-mwindows -static",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "\t$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS) -mwindows -static\n"
+        ));
+        assert_eq!(makefile.matches("-mwindows").count(), 1);
+    }
+
+    #[test]
+    fn lint_target_runs_linter_over_sources() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("src/util.c", &["src/util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--lint-target",
+                "--linter",
+                "cppcheck --enable=all",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("SOURCES := main.c src/util.c\n"));
+        assert!(makefile.contains("LINTER := cppcheck --enable=all\n"));
+        assert!(makefile.contains(
+            "lint:\n\t@for source in $(SOURCES); do $(LINTER) $$source || exit 1; done\n"
+        ));
+    }
+
+    #[test]
+    fn objcopy_produces_requested_formats() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "firmware",
+                "-e",
+                "c",
+                "--toolchain-prefix",
+                "arm-none-eabi-",
+                "--objcopy",
+                "ihex",
+                "--objcopy",
+                "binary",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CC := arm-none-eabi-gcc\n"));
+        assert!(makefile.contains("OBJCOPY := arm-none-eabi-objcopy\n"));
+        assert!(makefile.contains("all: binaries firmware.hex firmware.bin\n"));
+        assert!(makefile
+            .contains("firmware.hex: firmware\n\t$(OBJCOPY) -O ihex firmware firmware.hex\n"));
+        assert!(makefile
+            .contains("firmware.bin: firmware\n\t$(OBJCOPY) -O binary firmware firmware.bin\n"));
+        assert!(makefile.contains("rm -rf .OBJ firmware firmware.hex firmware.bin "));
+    }
+
+    #[test]
+    fn path_qualified_main_file_selects_the_program() {
+        let dep_map = dependency_map(&[
+            ("server/main.c", &["server/main.c"], true),
+            ("client/main.c", &["client/main.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--main-file", "server/main.c"],
+            dep_map.clone(),
+        );
+
+        assert!(makefile.contains("binaries: bin_client_main foo \n"));
+        assert!(makefile.contains("-o foo "));
+        assert!(makefile.contains(&format!(
+            "foo: $({}) | $(ODIR)",
+            object_file_dependencies_var_name("server/main")
+        )));
+
+        // A bare file name is ambiguous here, so neither of them becomes the program
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map);
+        assert!(makefile.contains("binaries: bin_client_main bin_server_main \n"));
+    }
+
+    #[test]
+    fn summary_json_reports_binaries_and_warnings() {
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("tools/gen.c", &["tools/gen.c"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let mut parse_result = ParseResult::new(dep_map, vec!["m".to_owned()]);
+        parse_result
+            .warnings
+            .push("could not find \"config.h\" included from main.c".to_owned());
+
+        let mut summary = Vec::new();
+        write_summary_json(&mut summary, &cli, &parse_result).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+
+        assert!(summary.contains("\"binary_count\": 2,"));
+        assert!(summary.contains("\"binaries\": [\"foo\", \"bin_tools_gen\"],"));
+        assert!(summary.contains("\"object_count\": 3,"));
+        assert!(summary.contains("\"libraries\": [\"m\"],"));
+        assert!(summary
+            .contains("\"warnings\": [\"could not find \\\"config.h\\\" included from main.c\"]"));
+    }
+
+    #[test]
+    fn header_only_library_gets_no_object_rules() {
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp", "geometry/vec.hpp"], true),
+            ("geometry/vec.hpp", &["geometry/mat.hpp"], false),
+            ("geometry/mat.hpp", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "cpp"], dep_map);
+
+        assert!(
+            makefile.contains("MAIN_SOURCE_DEPS := main.cpp geometry/vec.hpp geometry/mat.hpp\n")
+        );
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o\n"));
+        assert!(!makefile.contains("$(ODIR)/geometry"));
+        assert!(!makefile.contains("GEOMETRY_"));
+    }
+
+    #[test]
+    fn normalize_paths_emits_forward_slashes() {
+        let dep_map = dependency_map(&[
+            ("src\\main.c", &["src\\main.c", "src\\util\\str.h"], true),
+            (
+                "src\\util\\str.c",
+                &["src\\util\\str.c", "src\\util\\str.h"],
+                false,
+            ),
+            ("src\\util\\str.h", &[], false),
+            ("tools\\gen.c", &["tools\\gen.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--main-file", "src\\main.c"],
+            dep_map.clone(),
+        );
+
+        assert!(!makefile.contains('\\'));
+        assert!(
+            makefile.contains("SRC_MAIN_SOURCE_DEPS := src/main.c src/util/str.h src/util/str.c\n")
+        );
+        assert!(makefile.contains("$(CC) -c $(CFLAGS) src/util/str.c -o $(ODIR)/src_util_str.o"));
+        assert!(makefile.contains("binaries: foo bin_tools_gen \n"));
+
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--normalize-paths", "off"],
+            dep_map,
+        );
+        assert!(makefile.contains("$(CC) -c $(CFLAGS) src\\util\\str.c -o $(ODIR)/src_util_str.o"));
+    }
+
+    #[test]
+    fn objects_target_lists_all_object_files() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            ("tests/util_test.c", &["tests/util_test.c"], true),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--tests", "tests"], dep_map);
+
+        assert!(makefile.contains(
+            ".PHONY: objects\n\
+            objects: $(ODIR)/main.o $(ODIR)/tests_util_test.o $(ODIR)/util.o\n"
+        ));
+    }
+
+    #[test]
+    fn iquote_dirs_are_added_to_cflags() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--iquote",
+                "include",
+                "third_party/inc",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CFLAGS += -iquote include\nCFLAGS += -iquote third_party/inc\n"));
+    }
+
+    #[test]
+    fn fuzz_target_is_instrumented_and_separate() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "parse.h"], true),
+            ("parse.c", &["parse.c", "parse.h"], false),
+            ("parse.h", &[], false),
+            (
+                "fuzz/parse_fuzzer.c",
+                &["fuzz/parse_fuzzer.c", "parse.h"],
+                false,
+            ),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--fuzz", "fuzz/parse_fuzzer.c"],
+            dep_map,
+        );
+
+        assert!(makefile.contains("all: binaries\n"));
+        assert!(makefile.contains(
+            "fuzz: $(FUZZ_PARSE_FUZZER_SOURCE_DEPS)\n\
+            \t$(CC) $(CFLAGS) -fsanitize=fuzzer,address fuzz/parse_fuzzer.c parse.c -o fuzz $(LFLAGS)\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo fuzz "));
+    }
+
+    #[test]
+    fn relocatable_anchors_sources_to_makefile_dir() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util/str.h"], true),
+            ("util/str.c", &["util/str.c", "util/str.h"], false),
+            ("util/str.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--relocatable", "--iquote", "util"],
+            dep_map,
+        );
+
+        assert!(makefile.starts_with("SRCDIR := $(dir $(lastword $(MAKEFILE_LIST)))\n"));
+        assert!(makefile.contains("CFLAGS += -iquote $(SRCDIR)util\n"));
+        assert!(makefile.contains(
+            "MAIN_SOURCE_DEPS := $(SRCDIR)main.c $(SRCDIR)util/str.h $(SRCDIR)util/str.c\n"
+        ));
+        assert!(makefile.contains("$(CC) -c $(CFLAGS) $(SRCDIR)util/str.c -o $(ODIR)/util_str.o"));
+    }
+
+    #[test]
+    fn pie_compiles_and_links_main_binaries_as_pie() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/foo_test.c", &["tests/foo_test.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--pie", "--tests", "tests"],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CFLAGS += -fPIE\n"));
+        assert!(makefile.contains("$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS) -pie\n"));
+        assert_eq!(makefile.matches(" -pie").count(), 1);
+    }
+
+    #[test]
+    fn depfiles_are_written_to_depfile_dir() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util/str.h"], true),
+            ("util/str.c", &["util/str.c", "util/str.h"], false),
+            ("util/str.h", &[], false),
+        ]);
+
+        let makefile = generate(&["-b", "foo", "-e", "c", "--auto-deps"], dep_map.clone());
+        assert!(makefile.contains("$(CC) -c $(CFLAGS) -MMD -MP main.c -o $(ODIR)/main.o\n"));
+        assert!(makefile.ends_with("\n-include $(wildcard $(ODIR)/*.d)\n"));
+
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--auto-deps",
+                "--depfile-dir",
+                ".DEPS",
+            ],
+            dep_map,
+        );
+        assert!(makefile.contains("DEPDIR := .DEPS\n"));
+        assert!(makefile.contains("$(DEPDIR):\n\t@mkdir -p $(DEPDIR)\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/util_str.o: $(UTIL_STR_SOURCE_DEPS) | $(ODIR) $(DEPDIR)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP -MF $(DEPDIR)/util_str.d util/str.c -o $(ODIR)/util_str.o\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo $(DEPDIR) \n"));
+        assert!(makefile.ends_with("\n-include $(wildcard $(DEPDIR)/*.d)\n"));
+    }
+
+    #[test]
+    fn mirrored_objects_and_depfiles_keep_source_dirs() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "src/x.h"], true),
+            ("src/x.c", &["src/x.c", "src/x.h"], false),
+            ("src/x.h", &[], false),
+        ]);
+
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--auto-deps", "--mirror-dirs"],
+            dep_map.clone(),
+        );
+        // Without a depfile directory the compiler writes $(ODIR)/src/x.d next to the object file
+        assert!(makefile.contains(
+            "$(ODIR)/src/x.o: $(SRC_X_SOURCE_DEPS) | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP src/x.c -o $(ODIR)/src/x.o\n"
+        ));
+        assert!(makefile.ends_with("\n-include $(call rwildcard,$(ODIR),*.d)\n"));
+
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--auto-deps",
+                "--depfile-dir",
+                ".DEPS",
+                "--mirror-dirs",
+            ],
+            dep_map,
+        );
+        assert!(makefile.contains(
+            "$(ODIR)/src/x.o: $(SRC_X_SOURCE_DEPS) | $(ODIR) $(DEPDIR)\n\
+            \t@mkdir -p $(@D) $(dir $(DEPDIR)/src/x.d)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP -MF $(DEPDIR)/src/x.d src/x.c -o $(ODIR)/src/x.o\n"
+        ));
+        assert!(makefile.contains("rwildcard = $(foreach d,$(wildcard $(1:=/*)),"));
+        assert!(makefile.ends_with("\n-include $(call rwildcard,$(DEPDIR),*.d)\n"));
+    }
+
+    #[test]
+    fn build_profiles_keep_objects_apart() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--build-profiles", "--auto-deps"],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "BUILD ?= debug\n\
+            ifeq ($(BUILD),release)\n\
+            CFLAGS += -O2 -DNDEBUG\n\
+            else\n\
+            CFLAGS += -g\n\
+            endif\n"
+        ));
+        assert!(makefile
+            .contains("MAIN_OBJECT_DEPS := $(ODIR)/$(BUILD)/main.o $(ODIR)/$(BUILD)/util.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/$(BUILD)/util.o: $(UTIL_SOURCE_DEPS) | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP util.c -o $(ODIR)/$(BUILD)/util.o\n"
+        ));
+        assert!(makefile.ends_with("\n-include $(wildcard $(ODIR)/$(BUILD)/*.d)\n"));
+        assert!(makefile.contains(
+            "$(MODE_STAMP): FORCE | $(ODIR)\n\
+            \t@[ \"$$(cat $@ 2>/dev/null)\" = \"$(BUILD)\" ] || echo \"$(BUILD)\" > $@\n"
+        ));
+        assert!(makefile.contains("\nfoo: $(MAIN_OBJECT_DEPS) $(MODE_STAMP) | $(ODIR)\n"));
+    }
+
+    #[test]
+    fn long_compile_units_are_reported() {
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--warn-long-compile-units",
+            "2",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(
+            dependency_map(&[
+                ("main.c", &["a.h"], true),
+                ("util.c", &["c.h"], false),
+                ("a.h", &["b.h"], false),
+                ("b.h", &["c.h"], false),
+                ("c.h", &[], false),
+            ]),
+            Vec::new(),
+        );
+
+        assert_eq!(cli.warn_long_compile_units, Some(2));
+        assert_eq!(
+            long_compile_units(&cli, &parse_result, 2),
+            vec!["main.c includes 3 headers (more than 2), consider a precompiled header"]
+        );
+    }
+
+    #[test]
+    fn linker_links_binaries_while_compiler_builds_objects() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/test_main.c", &["tests/test_main.c"], true),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--compiler",
+                "clang",
+                "--linker",
+                "link-wrapper clang",
+                "--tests",
+                "tests",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CC := clang\n"));
+        assert!(makefile.contains("LINKER := link-wrapper clang\n"));
+        assert!(makefile.contains("\t$(LINKER) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS)\n"));
+        assert!(makefile
+            .contains("\t$(LINKER) $(CFLAGS) $(TESTS_TEST_MAIN_OBJECT_DEPS) -o tests/test_main\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) main.c -o $(ODIR)/main.o\n"));
+    }
+
+    #[test]
+    fn version_define_is_a_string_literal() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--version-from",
+                "VERSION",
+                "--dist",
+            ],
+            dep_map.clone(),
+        );
+        assert!(makefile.contains(
+            "VERSION := $(shell cat VERSION)\n\
+            CFLAGS += -DVERSION=\\\"$(VERSION)\\\"\n"
+        ));
+        // The dist archive is named after the same version
+        assert!(makefile.contains("VERSION ?= 0.1.0\n"));
+
+        let makefile = generate(&["-b", "foo", "-e", "c", "--version-from", "git"], dep_map);
+        assert!(makefile.contains("VERSION := $(shell git describe --tags)\n"));
+    }
+
+    #[test]
+    fn static_lib_leaves_out_mains_and_tests() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            (
+                "tests/test_util.c",
+                &["tests/test_util.c", "tests/helpers.h"],
+                true,
+            ),
+            (
+                "tests/helpers.c",
+                &["tests/helpers.c", "tests/helpers.h"],
+                false,
+            ),
+            ("tests/helpers.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--static-lib",
+                "foo",
+                "--tests",
+                "tests",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("all: binaries libfoo.a\n"));
+        assert!(makefile.contains(
+            "AR := ar\n\
+            LIBRARY_OBJECTS := $(ODIR)/util.o\n\n\
+            libfoo.a: $(LIBRARY_OBJECTS)\n\
+            \t$(AR) rcs libfoo.a $(LIBRARY_OBJECTS)\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo tests/test_util libfoo.a \n"));
+    }
+
+    #[test]
+    fn compdb_target_captures_every_object() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--emit-bear-db"], dep_map);
+
+        // The objects target compiles each source, so bear records an entry per source
+        assert!(makefile.contains("objects: $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains(
+            "BEAR := bear\n\n\
+            .PHONY: compdb\n\
+            compdb:\n\
+            \t$(BEAR) --output compile_commands.json -- $(MAKE) -B objects\n"
+        ));
+    }
+
+    #[test]
+    fn flat_layout_uses_base_names() {
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--flat"],
+            dependency_map(&[
+                ("src/main.c", &["src/main.c", "src/util/util.h"], true),
+                (
+                    "src/util/util.c",
+                    &["src/util/util.c", "src/util/util.h"],
+                    false,
+                ),
+                ("src/util/util.h", &[], false),
+            ]),
+        );
+
+        assert!(makefile.contains("UTIL_SOURCE_DEPS := src/util/util.c src/util/util.h\n"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/util.o: $(UTIL_SOURCE_DEPS) | $(ODIR)\n\
+            \t$(CC) -c $(CFLAGS) src/util/util.c -o $(ODIR)/util.o\n"
+        ));
+
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c", "--flat"]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(
+            dependency_map(&[
+                ("main.c", &["main.c"], true),
+                ("net/util.c", &["net/util.c"], false),
+                ("str/util.c", &["str/util.c"], false),
+            ]),
+            Vec::new(),
+        );
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Both net/util.c and str/util.c are named util, which --flat can't tell apart"
+        );
+    }
+
+    #[test]
+    fn run_targets_run_the_output_of_each_binary() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tools/gen.c", &["tools/gen.c"], true),
+            ("tests/parse.c", &["tests/parse.c"], true),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--tests", "tests"], dep_map);
+
+        assert!(makefile.contains(
+            ".PHONY: run-foo\n\
+            run-foo: foo\n\
+            \t./foo $(ARGS)\n"
+        ));
+        assert!(makefile.contains(
+            ".PHONY: run-bin_tools_gen\n\
+            run-bin_tools_gen: bin_tools_gen\n\
+            \t./tools/gen $(ARGS)\n"
+        ));
+        assert!(makefile.contains(
+            ".PHONY: run-tests_parse\n\
+            run-tests_parse: tests_parse\n\
+            \t./tests/parse $(ARGS)\n"
+        ));
+        assert!(!makefile.contains("run-%"));
+    }
+
+    #[test]
+    fn run_target_runs_main_program() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tools/gen.c", &["tools/gen.c"], true),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map);
+
+        assert!(makefile.contains(".PHONY: run\nrun: foo\n\t./foo $(ARGS)\n"));
+    }
+
+    #[test]
+    fn freestanding_has_no_default_standard() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+
+        let makefile = generate(
+            &["-b", "kernel", "-e", "c", "--freestanding"],
+            dep_map.clone(),
+        );
+        assert!(makefile.contains("CFLAGS := -Wall\nCFLAGS += -O0\n"));
+        assert!(makefile.contains("CFLAGS += -ffreestanding\nLFLAGS += -nostdlib\n"));
+        assert!(!makefile.contains("-std="));
+
+        let makefile = generate(
+            &["-b", "kernel", "-e", "c", "--freestanding", "--std", "c11"],
+            dep_map,
+        );
+        assert!(makefile.contains("CFLAGS += -std=c11\n"));
+    }
+
+    #[test]
+    fn extensionless_sources_are_compiled_by_language() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true), ("tool", &["tool"], true)]);
+
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--detect-by-content",
+                "--program",
+                "tool-bin=tool",
+            ],
+            dep_map.clone(),
+        );
+        assert!(makefile.contains("TOOL_OBJECT_DEPS := $(ODIR)/tool.o\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) -x c tool -o $(ODIR)/tool.o\n"));
+        assert!(makefile.contains("\t$(CC) $(CFLAGS) $(TOOL_OBJECT_DEPS) -o tool-bin $(LFLAGS)\n"));
+
+        let matches =
+            cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c", "--detect-by-content"]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(dep_map, Vec::new());
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The binary tool would overwrite the source tool, name it differently with --program NAME=tool"
+        );
+    }
+
+    #[test]
+    fn lto_applies_to_compiling_linking_and_archiving() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--lto", "--static-lib", "foo"],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CFLAGS += -flto\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) util.c -o $(ODIR)/util.o\n"));
+        assert!(makefile.contains("\t$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS)\n"));
+        assert!(makefile.contains("AR := gcc-ar\n"));
+    }
+
+    #[test]
+    fn failed_targets_are_deleted() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map);
+
+        assert!(makefile.starts_with(".DELETE_ON_ERROR:\n\nCC := gcc\n"));
+    }
+
+    #[test]
+    fn compiler_dep_makes_objects_depend_on_the_compiler() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--compiler-dep"], dep_map);
+
+        assert!(makefile.contains(
+            "CC_PATH := $(shell command -v $(CC))\n\
+            COMPILER_STAMP := $(ODIR)/compiler.stamp\n\n\
+            $(COMPILER_STAMP): $(CC_PATH) FORCE | $(ODIR)\n"
+        ));
+        assert!(
+            makefile.contains("$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) $(COMPILER_STAMP) | $(ODIR)\n")
+        );
+    }
+
+    #[test]
+    fn generated_header_is_a_prerequisite_of_each_consumer() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "version.h"], true),
+            ("about.c", &["about.c", "version.h"], false),
+            ("version.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--generated",
+                "version.h=./gen_version.sh > version.h",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("MAIN_SOURCE_DEPS := main.c version.h\n"));
+        assert!(makefile.contains("ABOUT_SOURCE_DEPS := about.c version.h\n"));
+        assert!(makefile.contains("$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) | $(ODIR)\n"));
+        assert!(makefile.contains("$(ODIR)/about.o: $(ABOUT_SOURCE_DEPS) | $(ODIR)\n"));
+        assert!(makefile.contains("version.h:\n\t./gen_version.sh > version.h\n"));
+        assert!(makefile.contains("rm -rf .OBJ foo version.h \n"));
+    }
+
+    #[test]
+    fn rpaths_are_added_to_link_flags_in_order() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--rpath",
+                "$$ORIGIN/../lib",
+                "--rpath",
+                "/opt/foo/lib",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "LFLAGS += '-Wl,-rpath,$$ORIGIN/../lib'\n\
+            LFLAGS += '-Wl,-rpath,/opt/foo/lib'\n"
+        ));
+
+        // Both the make-escaped and the plain form reach the linker as $ORIGIN
+        assert_eq!(rpath_flag("$ORIGIN/../lib"), "'-Wl,-rpath,$$ORIGIN/../lib'");
+        assert!(makefile.contains("$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS)\n"));
+    }
+
+    #[test]
+    fn programs_name_binaries_and_conflicts_are_reported() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tools/daemon.c", &["tools/daemon.c"], true),
+            ("old_main.c", &["old_main.c"], true),
+        ]);
+
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--program",
+                "foo-daemon=tools/daemon.c",
+            ],
+            dep_map.clone(),
+        );
+        assert!(makefile.contains("binaries: foo bin_old_main foo-daemon \n"));
+        assert!(makefile.contains("foo-daemon: $(TOOLS_DAEMON_OBJECT_DEPS) | $(ODIR)\n"));
+        assert!(makefile.contains("-o foo-daemon $(LFLAGS)\n"));
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--program",
+            "foo=old_main.c",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(dep_map, Vec::new());
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Both main.c and old_main.c define a main function and are built as the program foo"
+        );
+    }
+
+    #[test]
+    fn dist_target_packages_sources_and_headers() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util/str.h"], true),
+            ("util/str.c", &["util/str.c", "util/str.h"], false),
+            ("util/str.h", &["util/config.h"], false),
+            ("util/config.h", &[], false),
+        ]);
+
+        let makefile = generate(&["-b", "foo", "-e", "c", "--dist"], dep_map.clone());
+        assert!(makefile
+            .contains("DIST_FILES := Makefile main.c util/config.h util/str.c util/str.h\n"));
+        assert!(makefile.contains(
+            "VERSION ?= 0.1.0\n\
+            DIST_ARCHIVE := foo-$(VERSION).tar.gz\n\n\
+            .PHONY: dist\n\
+            dist: $(DIST_ARCHIVE)\n\n\
+            $(DIST_ARCHIVE): $(DIST_FILES)\n\
+            \ttar -czf $(DIST_ARCHIVE) $(DIST_FILES)\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo $(DIST_ARCHIVE) \n"));
+
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--dist", "--archive-format", "zip"],
+            dep_map,
+        );
+        assert!(makefile.contains("DIST_ARCHIVE := foo-$(VERSION).zip\n"));
+        assert!(makefile.contains("\tzip -q $(DIST_ARCHIVE) $(DIST_FILES)\n"));
+    }
+
+    #[test]
+    fn project_version_is_the_default_dist_version() {
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--dist",
+                "--project-version",
+                "1.2.0",
+            ],
+            dependency_map(&[("main.c", &[], true)]),
+        );
+        assert!(makefile.contains(
+            "VERSION ?= 1.2.0\n\
+            DIST_ARCHIVE := foo-$(VERSION).tar.gz\n"
+        ));
+    }
+
+    #[test]
+    fn compile_flags_lists_effective_flags() {
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "-e", "c", "--std", "c11", "--iquote", "include", "src",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+
+        assert_eq!(
+            compile_flags(&cli),
+            vec!["-Wall", "-std=c11", "-O0", "-iquote", "include", "-iquote", "src"]
+        );
+    }
+
+    #[test]
+    fn sort_by_deps_orders_object_rules_by_dependency_count() {
+        let dep_map = dependency_map(&[
+            ("app.c", &["app.c", "b.h", "c.h", "d.h"], true),
+            ("b.c", &["b.c", "c.h"], false),
+            ("c.c", &["c.c"], false),
+            ("a.c", &["a.c", "c.h"], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--sort-by-deps"], dep_map);
+
+        let rules: Vec<_> = makefile
+            .lines()
+            .filter(|line| line.starts_with("$(ODIR)/"))
+            .map(|line| &line[..line.find(':').unwrap()])
+            .collect();
+        assert_eq!(
+            rules,
+            vec!["$(ODIR)/c.o", "$(ODIR)/a.o", "$(ODIR)/b.o", "$(ODIR)/app.o"]
+        );
+    }
+
+    #[test]
+    fn skip_included_sources_compiles_them_only_once() {
+        let dep_map = dependency_map(&[("main.c", &["impl.c"], true), ("impl.c", &[], false)]);
+
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map.clone());
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/impl.o\n"));
+
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--skip-included-sources"],
+            dep_map,
+        );
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o\n"));
+        assert!(makefile.contains("MAIN_SOURCE_DEPS := main.c impl.c\n"));
+        assert!(!makefile.contains("$(ODIR)/impl.o"));
+    }
+
+    #[test]
+    fn tags_target_runs_ctags_over_source_roots() {
+        let dep_map = dependency_map(&[
+            ("src/main.c", &["src/main.c", "include/util.h"], true),
+            ("include/util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--tags-target",
+                "--ctags",
+                "uctags",
+                "--source-root",
+                "src",
+                "include",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CTAGS := uctags\n"));
+        assert!(makefile.contains(".PHONY: tags\ntags:\n\t$(CTAGS) -R src include\n"));
+        assert!(makefile.contains("clean:\n\trm -rf .OBJ foo tags \n"));
+    }
+
+    #[test]
+    fn touch_only_replaces_only_dependency_variables() {
+        let before = generate(
+            &["-b", "foo", "-e", "c"],
+            dependency_map(&[
+                ("main.c", &["main.c", "util.h"], true),
+                ("util.c", &["util.c", "util.h"], false),
+                ("util.h", &[], false),
+            ]),
+        );
+        let existing = format!(
+            "{}\n# Added by hand\nrun: foo\n\t./foo\n",
+            before.replace("CFLAGS += -O0", "CFLAGS += -O2")
+        );
+
+        let fresh = generate(
+            &["-b", "foo", "-e", "c"],
+            dependency_map(&[
+                ("main.c", &["main.c", "util.h", "log.h"], true),
+                ("util.c", &["util.c", "util.h"], false),
+                ("util.h", &[], false),
+                ("log.c", &["log.c", "log.h"], false),
+                ("log.h", &[], false),
+            ]),
+        );
+        let updated = update_dependency_variables(&existing, &fresh);
+
+        let changed: Vec<_> = existing
+            .lines()
+            .zip(updated.lines())
+            .filter(|(old, new)| old != new)
+            .map(|(_, new)| new)
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                "MAIN_SOURCE_DEPS := main.c util.h util.c log.h log.c",
+                "MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o $(ODIR)/log.o"
+            ]
+        );
+        assert_eq!(existing.lines().count(), updated.lines().count());
+        assert!(updated.contains("CFLAGS += -O2\n"));
+        assert!(updated.ends_with("# Added by hand\nrun: foo\n\t./foo\n"));
+    }
+
+    #[test]
+    fn print_deps_shows_transitive_closure() {
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("src/app.c", &["src/net.h"], true),
+            ("src/net.h", &["src/buffer.h"], false),
+            ("src/net.c", &["src/net.h"], false),
+            ("src/buffer.h", &[], false),
+            ("src/other.c", &[], false),
+        ]);
+        let parse_result = ParseResult::new(dep_map, Vec::new());
+
+        let mut out = Vec::new();
+        print_dependencies(&mut out, &cli, &parse_result, "./src/app.c").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "src/app.c\nsrc/net.h\nsrc/buffer.h\nsrc/net.c\n"
+        );
+        assert!(print_dependencies(&mut Vec::new(), &cli, &parse_result, "missing.c").is_err());
+    }
+
+    #[test]
+    fn dump_partitions_lists_files_by_partition() {
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--tests",
+            "tests",
+            "--dump-partitions",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/test_main.c", &["tests/test_main.c"], true),
+            ("util.c", &["util.c"], false),
+        ]);
+        let parse_result = ParseResult::new(dep_map, Vec::new());
+
+        let mut out = Vec::new();
+        print_partitions(&mut out, &cli, &parse_result).unwrap();
+
+        assert!(cli.dump_partitions);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "standalone:\n    main\ntests:\n    tests/test_main\nbenchmarks:\nexamples:\n"
+        );
+    }
+
+    #[test]
+    fn plan_summarizes_programs_tests_and_libraries() {
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--tests",
+            "tests",
+            "--print-plan",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            ("tests/test_a.c", &["tests/test_a.c"], true),
+            ("tests/test_b.c", &["tests/test_b.c"], true),
+            ("tests/test_c.c", &["tests/test_c.c"], true),
+        ]);
+        let parse_result = ParseResult::new(dep_map, vec!["m".to_owned(), "pthread".to_owned()]);
+
+        let mut out = Vec::new();
+        print_plan(&mut out, &cli, &parse_result).unwrap();
+
+        assert!(cli.print_plan);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Program foo from 2 sources, 3 tests, links -lm -lpthread\n"
+        );
+    }
+
+    #[test]
+    fn automake_lists_program_sources() {
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "-e", "c", "--tests", "tests", "--format", "automake",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util/str.h"], true),
+            ("util/str.c", &["util/str.c", "util/str.h"], false),
+            ("util/str.h", &[], false),
+            ("tools/gen-table.c", &["tools/gen-table.c"], true),
+            (
+                "tests/str_test.c",
+                &["tests/str_test.c", "util/str.h"],
+                true,
+            ),
+        ]);
+        let parse_result = ParseResult::new(dep_map, vec!["m".to_owned()]);
+
+        let mut out = Vec::new();
+        write_automake(&mut out, &cli, &parse_result).unwrap();
+        let makefile_am = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            makefile_am,
+            "AM_CFLAGS = -Wall -std=c99 -O0\n\
+            LDADD = -lm\n\
+            \n\
+            bin_PROGRAMS = foo tools/gen-table\n\
+            foo_SOURCES = main.c util/str.h util/str.c\n\
+            tools_gen_table_SOURCES = tools/gen-table.c\n\
+            \n\
+            check_PROGRAMS = tests/str_test\n\
+            tests_str_test_SOURCES = tests/str_test.c util/str.h util/str.c\n\
+            \n\
+            TESTS = $(check_PROGRAMS)\n"
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "--mixed", "--std", "c++17", "--opt", "O2", "--format",
+            "automake",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let parse_result = ParseResult::new(dep_map, Vec::new());
+
+        let mut out = Vec::new();
+        write_automake(&mut out, &cli, &parse_result).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with(
+            "AM_CFLAGS = -Wall -std=c99 -O2\n\
+            AM_CXXFLAGS = -Wall -std=c++17 -O2\n"
+        ));
+    }
+
+    #[test]
+    fn meson_build_declares_the_programs() {
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "-e", "c", "--tests", "tests", "--format", "meson",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util/str.h"], true),
+            ("util/str.c", &["util/str.c", "util/str.h"], false),
+            ("util/str.h", &[], false),
+            (
+                "tests/str_test.c",
+                &["tests/str_test.c", "util/str.h"],
+                true,
+            ),
+        ]);
+        let parse_result = ParseResult::new(dep_map, vec!["m".to_owned(), "pthread".to_owned()]);
+
+        let mut out = Vec::new();
+        write_meson(&mut out, &cli, &parse_result).unwrap();
+        let meson_build = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            meson_build,
+            "project('foo', 'c', default_options : ['warning_level=1', 'optimization=0', 'c_std=c99'])\n\
+            \n\
+            cc = meson.get_compiler('c')\n\
+            deps = [cc.find_library('m'), dependency('threads')]\n\
+            \n\
+            foo = executable('foo', 'main.c', 'util/str.h', 'util/str.c', dependencies : deps)\n\
+            tests_str_test = executable('tests_str_test', 'tests/str_test.c', 'util/str.h', 'util/str.c', dependencies : deps)\n\
+            \n\
+            test('tests/str_test', tests_str_test)\n"
+        );
+    }
+
+    #[test]
+    fn object_recipe_replaces_the_default_for_that_object_only() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "parser.h"], true),
+            ("parser.c", &["parser.c", "parser.h"], false),
+            ("parser.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--object-recipe",
+                "./parser.c=\"$(CC) -c $(CFLAGS) -Wno-unused-function $< -o $@\"",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "$(ODIR)/parser.o: $(PARSER_SOURCE_DEPS) | $(ODIR)\n\
+            \t$(CC) -c $(CFLAGS) -Wno-unused-function $< -o $@\n"
+        ));
+        assert!(makefile.contains(
+            "$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) | $(ODIR)\n\
+            \t$(CC) -c $(CFLAGS) main.c -o $(ODIR)/main.o\n"
+        ));
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--object-recipe",
+            "parser.h=true",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(
+            dependency_map(&[("main.c", &["main.c"], true), ("parser.h", &[], false)]),
+            Vec::new(),
+        );
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "parser.h given with --object-recipe is not one of the source files"
+        );
+    }
+
+    #[test]
+    fn include_mk_pulls_in_fragments_at_the_top() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--include-mk", "rules/*.mk"],
+            dep_map,
+        );
+
+        assert!(makefile.starts_with(
+            ".DELETE_ON_ERROR:\n\n\
+            -include $(wildcard rules/*.mk)\n\
+            .DEFAULT_GOAL := all\n\n\
+            CC := gcc\n"
+        ));
+    }
+
+    #[test]
+    fn link_jobs_chain_the_link_steps() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tool.c", &["tool.c"], true),
+            ("tests/a.c", &["tests/a.c"], true),
+            ("tests/b.c", &["tests/b.c"], true),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--tests",
+                "tests",
+                "--link-jobs",
+                "2",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("tests_a: | foo\ntests_b: | bin_tool\n\n"));
+        assert!(!makefile.contains("bin_tool: |"));
+    }
+
+    #[test]
+    fn echo_includes_prints_the_source_dependencies() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--echo-includes"], dep_map);
+
+        assert!(makefile.contains(
+            "$(ODIR)/main.o: $(MAIN_SOURCE_DEPS) | $(ODIR)\n\
+            \t@echo \"$@: $(MAIN_SOURCE_DEPS)\" >&2\n\
+            \t$(CC) -c $(CFLAGS) main.c -o $(ODIR)/main.o\n"
+        ));
+    }
+
+    #[test]
+    fn test_runner_links_the_test_files() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/runner.c", &["tests/runner.c"], true),
+            (
+                "tests/list_test.c",
+                &["tests/list_test.c", "list.h", "list.c"],
+                false,
+            ),
+            ("tests/str_test.c", &["tests/str_test.c"], false),
+            ("list.c", &["list.c", "list.h"], false),
+            ("list.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--tests",
+                "tests",
+                "--test-runner",
+                "tests/runner.c",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "TESTS_RUNNER_OBJECT_DEPS := $(ODIR)/tests_runner.o $(ODIR)/tests_list_test.o \
+            $(ODIR)/list.o $(ODIR)/tests_str_test.o\n"
+        ));
+        assert!(makefile.contains("tests: tests_runner \n"));
+    }
+
+    #[test]
+    fn explain_reports_excluded_file() {
+        let root_dir = tempfile::tempdir().unwrap();
+        fs::write(root_dir.path().join("main.c"), "int main() {}\n").unwrap();
+        fs::write(root_dir.path().join("scratch.c"), "int main() {}\n").unwrap();
+        fs::create_dir(root_dir.path().join("vendor")).unwrap();
+        fs::write(
+            root_dir.path().join("vendor/lib.c"),
+            "int lib() { return 0; }\n",
+        )
+        .unwrap();
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--exclude-file",
+            "scratch.c",
+            "--exclude",
+            "vendor",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let parser = Parser::new(root_dir.path().to_path_buf(), &cli);
+        let parse_result = parser.parse().unwrap();
+
+        let mut out = Vec::new();
+        explain_file(&mut out, &cli, &parser, &parse_result, "./scratch.c").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "scratch.c\n\
+            \x20   Source file: yes, it matches the extension c\n\
+            \x20   Excluded: yes, by --exclude-file\n"
+        );
+
+        let mut out = Vec::new();
+        explain_file(&mut out, &cli, &parser, &parse_result, "vendor/lib.c").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "vendor/lib.c\n\
+            \x20   Source file: yes, it matches the extension c\n\
+            \x20   Excluded: yes, by --exclude vendor\n"
+        );
+
+        let mut out = Vec::new();
+        explain_file(&mut out, &cli, &parser, &parse_result, "main.c").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main.c\n\
+            \x20   Source file: yes, it matches the extension c\n\
+            \x20   Excluded: no\n\
+            \x20   Partition: standalone, built as foo\n\
+            \x20   Dependencies: none\n"
+        );
+    }
+
+    #[test]
+    fn each_test_can_be_run_by_name() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/list.c", &["tests/list.c"], true),
+            ("tests/unit/str.c", &["tests/unit/str.c"], true),
+            (
+                "tests/integration/str.c",
+                &["tests/integration/str.c"],
+                true,
+            ),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--tests", "tests"], dep_map);
+
+        assert!(makefile.contains(
+            ".PHONY: test-list\n\
+            test-list: tests_list\n\
+            \t./tests/list $(ARGS)\n"
+        ));
+        assert!(makefile.contains(
+            "test-tests_unit_str: tests_unit_str\n\
+            \t./tests/unit/str $(ARGS)\n"
+        ));
+        assert!(makefile.contains("test-tests_integration_str: tests_integration_str\n"));
+    }
+
+    #[test]
+    fn cpp_covers_every_cpp_source_extension() {
+        let dep_map = dependency_map(&[
+            ("main.cc", &["main.cc", "util.h"], true),
+            ("util.cxx", &["util.cxx", "util.h"], false),
+            ("util.h", &[], false),
+            ("tool.C", &["tool.C"], true),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "cc"], dep_map);
+
+        assert!(makefile.contains("CC := g++\n"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) main.cc -o $(ODIR)/main.o\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) util.cxx -o $(ODIR)/util.o\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) tool.C -o $(ODIR)/tool.o\n"));
+    }
+
+    #[test]
+    fn cpp_headers_are_paired_with_their_sources() {
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp", "widget.hpp", "canvas.cpp"], true),
+            ("widget.cpp", &["widget.cpp", "widget.hpp"], false),
+            ("widget.hpp", &[], false),
+            ("canvas.cpp", &["canvas.cpp"], false),
+            ("canvas.hh", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "cpp"], dep_map);
+
+        assert!(makefile
+            .contains("MAIN_SOURCE_DEPS := main.cpp widget.hpp widget.cpp canvas.cpp canvas.hh\n"));
+        assert!(makefile
+            .contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/widget.o $(ODIR)/canvas.o\n"));
+    }
+
+    #[test]
+    fn mixed_projects_compile_each_language_with_its_compiler() {
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "--mixed", "--std", "c++17"], dep_map);
+
+        assert!(makefile.contains("CC := gcc\nCXX := g++\nCFLAGS := -Wall\nCFLAGS += -O0\n"));
+        assert!(makefile.contains("CXXFLAGS := $(CFLAGS) -std=c++17\nCFLAGS += -std=c99\n"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains("\t$(CXX) -c $(CXXFLAGS) main.cpp -o $(ODIR)/main.o\n"));
+        assert!(makefile.contains("\t$(CC) -c $(CFLAGS) util.c -o $(ODIR)/util.o\n"));
+        assert!(makefile.contains("\t$(CXX) $(CXXFLAGS) $(MAIN_OBJECT_DEPS) -o foo $(LFLAGS)\n"));
+
+        let dep_map = dependency_map(&[("main.cpp", &["main.cpp"], true)]);
+        let makefile = generate(
+            &["-b", "foo", "--mixed", "-c", "clang++-15", "--c-std", "c11"],
+            dep_map,
+        );
+        assert!(makefile.contains("CC := clang-15\nCXX := clang++-15\n"));
+        assert!(makefile.contains("CXXFLAGS := $(CFLAGS) -std=c++11\nCFLAGS += -std=c11\n"));
+    }
+
+    #[test]
+    fn include_dirs_are_added_to_cflags() {
+        let dep_map = dependency_map(&[("main.c", &["main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--include-dir",
+                "include",
+                "--include-dir",
+                "lib/inc",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CFLAGS += -Iinclude\nCFLAGS += -Ilib/inc\n"));
+    }
+
+    #[test]
+    fn cflags_are_appended_to_cflags() {
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--cflags",
+                "-Wextra -fno-exceptions",
+            ],
+            dependency_map(&[("main.c", &[], true)]),
+        );
+        assert!(makefile.contains("CFLAGS += -O0\n"));
+        assert!(makefile.contains("CFLAGS += -Wextra -fno-exceptions\n"));
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--cflags",
+            "-Wextra -fno-exceptions",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        assert_eq!(
+            compile_flags(&cli),
+            vec!["-Wall", "-std=c99", "-O0", "-Wextra", "-fno-exceptions"]
+        );
+    }
+
+    #[test]
+    fn ldflags_are_appended_to_lflags() {
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--ldflags", "-L/opt/lib"],
+            dependency_map(&[("main.c", &[], true)]),
+        );
+        assert!(makefile.contains("LFLAGS += -L/opt/lib\n"));
+    }
+
+    #[test]
+    fn defines_are_added_to_cflags() {
+        let makefile = generate(
+            &[
+                "-b", "foo", "-e", "c", "--define", "NDEBUG", "--define", "FOO=1",
+            ],
+            dependency_map(&[("main.c", &[], true)]),
+        );
+        assert!(makefile.contains("CFLAGS += -DNDEBUG\nCFLAGS += -DFOO=1\n"));
+    }
+
+    #[test]
+    fn debug_switch_keeps_objects_apart() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--opt", "O2", "--debug-switch"],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "CFLAGS += -O2\n\
+            LFLAGS := \n\
+            DEBUG ?= 0\n\
+            ifeq ($(DEBUG),1)\n\
+            MODE := debug\n\
+            CFLAGS += -O0 -g\n\
+            else\n\
+            MODE := release\n\
+            endif\n"
+        ));
+        assert!(makefile
+            .contains("MAIN_OBJECT_DEPS := $(ODIR)/$(MODE)/main.o $(ODIR)/$(MODE)/util.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/$(MODE)/util.o: $(UTIL_SOURCE_DEPS) | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n"
+        ));
+        assert!(makefile.contains(
+            "MODE_STAMP := $(ODIR)/mode.stamp\n\n\
+            $(MODE_STAMP): FORCE | $(ODIR)\n\
+            \t@[ \"$$(cat $@ 2>/dev/null)\" = \"$(MODE)\" ] || echo \"$(MODE)\" > $@\n\n\
+            FORCE:\n"
+        ));
+        assert!(makefile.contains("\nfoo: $(MAIN_OBJECT_DEPS) $(MODE_STAMP) | $(ODIR)\n"));
+    }
+
+    #[test]
+    fn sanitizer_targets_build_into_their_own_directories() {
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--sanitizers"],
+            dependency_map(&[("main.c", &[], true)]),
+        );
+
+        assert!(makefile.contains("CFLAGS += $(SANITIZE_FLAGS)\n"));
+        assert!(makefile.contains(
+            ".PHONY: asan\n\
+            asan:\n\
+            \t@mkdir -p $(ODIR)/asan\n\
+            \t$(MAKE) ODIR=$(ODIR)/asan BIN_SUFFIX=-asan SANITIZE_FLAGS=\"-g -fsanitize=address -fno-omit-frame-pointer\"\n"
+        ));
+        assert!(makefile.contains(
+            "tsan:\n\
+            \t@mkdir -p $(ODIR)/tsan\n\
+            \t$(MAKE) ODIR=$(ODIR)/tsan BIN_SUFFIX=-tsan SANITIZE_FLAGS=\"-g -fsanitize=thread\"\n"
+        ));
+        assert!(makefile.contains("binaries: foo$(BIN_SUFFIX) \n"));
+        assert!(makefile.contains(
+            "foo$(BIN_SUFFIX): $(MAIN_OBJECT_DEPS) | $(ODIR)\n\
+            \t$(CC) $(CFLAGS) $(MAIN_OBJECT_DEPS) -o foo$(BIN_SUFFIX) $(LFLAGS)\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo foo-asan foo-ubsan foo-tsan \n"));
+    }
+
+    #[test]
+    fn format_targets_cover_sources_and_headers() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h", "version.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            ("version.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--format-targets",
+                "--generated",
+                "version.h=./gen_version.sh",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("FORMAT_FILES := main.c util.c util.h\n"));
+        assert!(makefile.contains(
+            ".PHONY: format format-check\n\
+            format:\n\
+            \t$(CLANG_FORMAT) -i $(FORMAT_FILES)\n\n\
+            format-check:\n\
+            \t$(CLANG_FORMAT) --dry-run --Werror $(FORMAT_FILES)\n"
+        ));
+    }
+
+    #[test]
+    fn tidy_target_uses_compile_flags() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--tidy-target"], dep_map);
+
+        assert!(makefile.contains("SOURCES := main.c util.c\n"));
+        assert!(makefile.contains(
+            ".PHONY: tidy\n\
+            tidy:\n\
+            \t$(CLANG_TIDY) $(SOURCES) -- $(CFLAGS)\n"
+        ));
+    }
+
+    #[test]
+    fn install_targets_install_binaries_library_and_headers() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "include/util.h"], true),
+            ("util.c", &["util.c", "include/util.h"], false),
+            ("include/util.h", &[], false),
+            (
+                "tests/util_test.c",
+                &["tests/util_test.c", "tests/helpers.h"],
+                true,
+            ),
+            ("tests/helpers.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--tests",
+                "tests",
+                "--iquote",
+                "include",
+                "--static-lib",
+                "util",
+                "--install",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "PREFIX ?= /usr/local\n\n\
+            .PHONY: install uninstall\n\
+            install: binaries libutil.a\n\
+            \tinstall -D -m 755 foo $(DESTDIR)$(PREFIX)/bin/foo\n\
+            \tinstall -D -m 644 libutil.a $(DESTDIR)$(PREFIX)/lib/libutil.a\n\
+            \tinstall -D -m 644 include/util.h $(DESTDIR)$(PREFIX)/include/util.h\n\n\
+            uninstall:\n\
+            \trm -f $(DESTDIR)$(PREFIX)/bin/foo\n\
+            \trm -f $(DESTDIR)$(PREFIX)/lib/libutil.a\n\
+            \trm -f $(DESTDIR)$(PREFIX)/include/util.h\n"
+        ));
+    }
+
+    #[test]
+    fn installing_library_requires_static_lib() {
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--install",
+            "library",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(dependency_map(&[("main.c", &[], true)]), Vec::new());
+
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Installing the library requires --static-lib or --lib"
+        );
+    }
+
+    #[test]
+    fn static_lib_mode_links_against_the_archive() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            (
+                "tests/test_util.c",
+                &["tests/test_util.c", "util.h", "tests/helpers.h"],
+                true,
+            ),
+            (
+                "tests/helpers.c",
+                &["tests/helpers.c", "tests/helpers.h"],
+                false,
+            ),
+            ("tests/helpers.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b", "foo", "-e", "c", "--lib", "static", "--tests", "tests",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("all: binaries libfoo.a\n"));
+        assert!(makefile.contains("LIBRARY_OBJECTS := $(ODIR)/util.o\n"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o libfoo.a\n"));
+        assert!(makefile.contains(
+            "TESTS_TEST_UTIL_OBJECT_DEPS := $(ODIR)/tests_test_util.o $(ODIR)/tests_helpers.o libfoo.a\n"
+        ));
+    }
+
+    #[test]
+    fn shared_lib_mode_builds_position_independent_library() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--lib", "shared", "--install"],
+            dep_map,
+        );
+
+        assert!(makefile.contains("CFLAGS += -fPIC\n"));
+        assert!(makefile.contains("all: binaries libfoo.so\n"));
+        assert!(makefile.contains(
+            "LIBRARY_OBJECTS := $(ODIR)/util.o\n\n\
+            libfoo.so: $(LIBRARY_OBJECTS)\n\
+            \t$(CC) -shared $(CFLAGS) $(LIBRARY_OBJECTS) -o libfoo.so $(LFLAGS)\n"
+        ));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains(
+            "install: binaries libfoo.so\n\
+            \tinstall -D -m 755 foo $(DESTDIR)$(PREFIX)/bin/foo\n\
+            \tinstall -D -m 755 libfoo.so $(DESTDIR)$(PREFIX)/lib/libfoo.so\n\
+            \tinstall -D -m 644 util.h $(DESTDIR)$(PREFIX)/include/util.h\n"
+        ));
+        assert!(makefile.contains("rm -rf .OBJ foo libfoo.so \n"));
+    }
+
+    #[test]
+    fn mixed_shared_lib_is_linked_as_cpp() {
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "cpp", "--mixed", "--lib", "shared"],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "libfoo.so: $(LIBRARY_OBJECTS)\n\
+            \t$(CXX) -shared $(CXXFLAGS) $(LIBRARY_OBJECTS) -o libfoo.so $(LFLAGS)\n"
+        ));
+    }
+
+    #[test]
+    fn run_benchmarks_collects_results() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("benches/sort.c", &["benches/sort.c"], true),
+            ("benches/hash.c", &["benches/hash.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--benchmarks", "benches"],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            ".PHONY: run-benchmarks\n\
+            run-benchmarks: benchmarks\n\
+            \t@mkdir -p bench-results\n\
+            \t@stamp=$$(date +%Y%m%d-%H%M%S); \\\n\
+            \t./benches/hash > bench-results/benches_hash-$$stamp.txt && cat bench-results/benches_hash-$$stamp.txt && \\\n\
+            \t./benches/sort > bench-results/benches_sort-$$stamp.txt && cat bench-results/benches_sort-$$stamp.txt\n"
+        ));
+    }
+
+    #[test]
+    fn memcheck_runs_main_program_and_tests_under_valgrind() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c"], true),
+            ("tests/test_foo.c", &["tests/test_foo.c"], true),
+        ]);
+        let makefile = generate(
+            &["-b", "foo", "-e", "c", "--tests", "tests", "--valgrind"],
+            dep_map,
+        );
+
+        assert!(makefile.contains(
+            "VALGRIND := valgrind --error-exitcode=1 --leak-check=full\n\n\
+            .PHONY: memcheck\n\
+            memcheck: foo tests\n\
+            \t$(VALGRIND) ./foo $(ARGS)\n\
+            \t$(VALGRIND) ./tests/test_foo\n"
+        ));
+    }
+
+    #[test]
+    fn directories_are_order_only_prerequisites() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c"], dep_map);
+
+        assert!(makefile.contains("$(ODIR):\n\t@mkdir -p $(ODIR)\n"));
+        assert!(makefile.contains("foo: $(MAIN_OBJECT_DEPS) | $(ODIR)\n"));
+        assert!(makefile.contains("$(ODIR)/util.o: $(UTIL_SOURCE_DEPS) | $(ODIR)\n"));
+    }
+
+    #[test]
+    fn sources_compiled_to_the_same_object_are_reported() {
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(
+            dependency_map(&[
+                ("main.c", &["main.c"], true),
+                ("a/b_c.c", &["a/b_c.c"], false),
+                ("a_b/c.c", &["a_b/c.c"], false),
+            ]),
+            Vec::new(),
+        );
+
+        let error = write_makefile(&mut Vec::new(), &cli, &parse_result).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Both a/b_c.c and a_b/c.c are compiled to $(ODIR)/a_b_c.o"
+        );
+    }
+
+    #[test]
+    fn compiler_depmode_drops_source_dependency_variables() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "util.h", "version.h"], true),
+            ("util.c", &["util.c", "util.h"], false),
+            ("util.h", &[], false),
+            ("version.h", &[], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "c",
+                "--depmode",
+                "compiler",
+                "--generated",
+                "version.h=./gen_version.sh",
+            ],
+            dep_map,
+        );
+
+        assert!(!makefile.contains("_SOURCE_DEPS"));
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/util.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/main.o: main.c version.h | $(ODIR)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP main.c -o $(ODIR)/main.o\n"
+        ));
+        assert!(makefile.contains("$(ODIR)/util.o: util.c | $(ODIR)\n"));
+        assert!(makefile.ends_with("\n-include $(wildcard $(ODIR)/*.d)\n"));
+    }
+
+    #[test]
+    fn compact_mode_compiles_with_pattern_rules() {
+        let dep_map = dependency_map(&[
+            ("main.c", &["main.c", "src/util.h"], true),
+            ("src/util.c", &["src/util.c", "src/util.h"], false),
+            ("src/util.h", &[], false),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "c", "--compact"], dep_map);
+
+        assert!(makefile.contains("MAIN_OBJECT_DEPS := $(ODIR)/main.o $(ODIR)/src/util.o\n"));
+        assert!(makefile.contains(
+            "$(ODIR)/main.o: $(MAIN_SOURCE_DEPS)\n\
+            $(ODIR)/src/util.o: $(SRC_UTIL_SOURCE_DEPS)\n\n\
+            $(ODIR)/%.o: %.c | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n\
+            \t$(CC) -c $(CFLAGS) $< -o $@\n"
+        ));
+        assert!(!makefile.contains("-o $(ODIR)/main.o"));
+
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp"], true),
+            ("util.c", &["util.c"], false),
+        ]);
+        let makefile = generate(
+            &[
+                "-b",
+                "foo",
+                "-e",
+                "cpp",
+                "--mixed",
+                "--compact",
+                "--depmode",
+                "compiler",
+            ],
+            dep_map,
+        );
+        assert!(!makefile.contains("$(ODIR)/main.o:"));
+        assert!(makefile.contains(
+            "$(ODIR)/%.o: %.cpp | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n\
+            \t$(CXX) -c $(CXXFLAGS) -MMD -MP $< -o $@\n"
+        ));
+        assert!(makefile.contains(
+            "$(ODIR)/%.o: %.c | $(ODIR)\n\
+            \t@mkdir -p $(@D)\n\
+            \t$(CC) -c $(CFLAGS) -MMD -MP $< -o $@\n"
+        ));
+    }
+
+    #[test]
+    fn mixed_projects_get_per_language_targets() {
+        let dep_map = dependency_map(&[
+            ("main.cpp", &["main.cpp"], true),
+            ("tools/gen.c", &["tools/gen.c"], true),
+            ("tools/dump.cc", &["tools/dump.cc"], true),
+        ]);
+        let makefile = generate(&["-b", "foo", "-e", "cpp", "--mixed"], dep_map);
+
+        assert!(makefile.contains(".PHONY: all-c\nall-c: bin_tools_gen\n"));
+        assert!(makefile.contains(".PHONY: all-cpp\nall-cpp: foo bin_tools_dump\n"));
+    }
+
+    #[test]
+    fn explain_names_both_languages_of_mixed_projects() {
+        let root_dir = tempfile::tempdir().unwrap();
+        fs::write(root_dir.path().join("main.cpp"), "int main() {}\n").unwrap();
+        fs::write(root_dir.path().join("notes.txt"), "notes\n").unwrap();
+
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "--mixed"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let parser = Parser::new(root_dir.path().to_path_buf(), &cli);
+        let parse_result = parser.parse().unwrap();
+
+        let mut out = Vec::new();
+        explain_file(&mut out, &cli, &parser, &parse_result, "notes.txt").unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("    Source file: no, it doesn't match the extensions of c and c++\n"));
+    }
+
+    #[test]
+    fn meson_maps_ofast_to_o3_with_fast_math() {
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "-e", "c", "--opt", "Ofast", "--format", "meson",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result =
+            ParseResult::new(dependency_map(&[("main.c", &["main.c"], true)]), Vec::new());
+
+        let mut out = Vec::new();
+        write_meson(&mut out, &cli, &parse_result).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with(
+            "project('foo', 'c', default_options : ['warning_level=1', 'optimization=3', 'c_std=c99', 'c_args=-ffast-math'])\n"
+        ));
+    }
+
+    #[test]
+    fn objcopy_converts_the_main_program_and_keeps_its_suffix() {
+        let dep_map = dependency_map(&[("app/main.c", &["app/main.c"], true)]);
+        let makefile = generate(
+            &[
+                "-b",
+                "firmware",
+                "-e",
+                "c",
+                "--main-file",
+                "app/main.c",
+                "--program",
+                "fw=app/main.c",
+                "--objcopy",
+                "binary",
+                "--sanitizers",
+            ],
+            dep_map,
+        );
+
+        assert!(makefile.contains("all: binaries fw$(BIN_SUFFIX).bin\n"));
+        assert!(makefile.contains(
+            "fw$(BIN_SUFFIX).bin: fw$(BIN_SUFFIX)\n\
+            \t$(OBJCOPY) -O binary fw$(BIN_SUFFIX) fw$(BIN_SUFFIX).bin\n"
+        ));
+        assert!(makefile.contains("fw.bin fw-asan.bin fw-ubsan.bin fw-tsan.bin "));
+    }
+
+    #[test]
+    fn meson_sets_the_standard_of_each_language_in_mixed_projects() {
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "--mixed", "--std", "c++17", "--c-std", "c11", "--format",
+            "meson",
+        ]);
+        let cli = Cli::from_matches(&matches, Path::new(".")).unwrap();
+        let parse_result = ParseResult::new(
+            dependency_map(&[
+                ("main.cpp", &["main.cpp", "util.h"], true),
+                ("util.c", &["util.c", "util.h"], false),
+                ("util.h", &[], false),
+            ]),
+            Vec::new(),
+        );
+
+        let mut out = Vec::new();
+        write_meson(&mut out, &cli, &parse_result).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with(
+            "project('foo', ['c', 'cpp'], default_options : ['warning_level=1', 'optimization=0', 'cpp_std=c++17', 'c_std=c11'])\n"
+        ));
+    }
 }
@@ -1,19 +1,29 @@
-use crate::{cli::Cli, filename_utils::*};
+use crate::{
+    cache::{content_hash, CacheEntry, ParseCache, CACHE_FILE},
+    cli::Cli,
+    filename_utils::*,
+};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
     fs,
-    path::{Path, PathBuf},
+    io::Write,
+    path::{Component, Path, PathBuf},
 };
 use walkdir::{DirEntry, WalkDir};
 
 // The bool indicates whether the key (source file) has a main function in it or not
-pub type DependencyMap = HashMap<String, (Vec<String>, bool)>;
+pub type DependencyMap = BTreeMap<String, (Vec<String>, bool)>;
 
 #[derive(Debug)]
 pub struct ParseResult {
     pub dependency_map: DependencyMap,
     pub dlls: Vec<String>,
+    // The user includes that couldn't be resolved along with the file including them
+    pub missing_includes: Vec<(String, String)>,
+    // The headers with neither an include guard nor #pragma once
+    pub unguarded_headers: Vec<String>,
+    pub warnings: Vec<String>,
 }
 
 pub struct Parser<'cli> {
@@ -25,6 +35,20 @@ struct ParseContext<'c> {
     dependency_map: &'c mut DependencyMap,
     dlls: &'c mut Vec<String>,
     seen: HashSet<String>,
+    cache: Option<&'c mut ParseCache>,
+    modules: Option<&'c mut Modules>,
+    missing_includes: &'c mut Vec<(String, String)>,
+    unguarded_headers: &'c mut Vec<String>,
+    // Whether any of the parsed files calls a function of the math library
+    uses_math: bool,
+    cli: &'c Cli<'c>,
+}
+
+// Maps C++20 module names to the interface units exporting them and files to the modules they import
+#[derive(Default)]
+struct Modules {
+    interfaces: HashMap<String, String>,
+    imports: HashMap<String, Vec<String>>,
 }
 
 // These are some default mappings for dynamic linked libraries
@@ -43,48 +67,518 @@ impl ParseResult {
         Self {
             dependency_map,
             dlls,
+            missing_includes: Vec::new(),
+            unguarded_headers: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    // Fails listing all of the unresolved includes, if any
+    pub fn ensure_includes_resolved(&self) -> Result<(), Box<dyn Error>> {
+        if self.missing_includes.is_empty() {
+            return Ok(());
         }
+
+        let listing = self
+            .missing_includes
+            .iter()
+            .map(|(file, include_file)| format!("    {} included from {}", include_file, file))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Err(format!("Could not resolve the following includes:\n{}", listing).into())
+    }
+
+    // The warnings to print, truncated after the given number of them with a summary of the rest
+    pub fn displayed_warnings(&self, max_warnings: Option<usize>) -> Vec<String> {
+        let max_warnings = match max_warnings {
+            Some(max_warnings) if max_warnings < self.warnings.len() => max_warnings,
+            _ => return self.warnings.clone(),
+        };
+
+        let mut warnings = self.warnings[..max_warnings].to_vec();
+        warnings.push(format!("… and {} more", self.warnings.len() - max_warnings));
+        warnings
+    }
+
+    // Fails listing all of the headers without an include guard, if any
+    pub fn ensure_headers_guarded(&self) -> Result<(), Box<dyn Error>> {
+        if self.unguarded_headers.is_empty() {
+            return Ok(());
+        }
+
+        let listing = self
+            .unguarded_headers
+            .iter()
+            .map(|header| format!("    {}", header))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Err(format!(
+            "The following headers have neither an include guard nor #pragma once:\n{}",
+            listing
+        )
+        .into())
     }
 }
 
 impl<'c> ParseContext<'c> {
-    pub fn new(dependency_map: &'c mut DependencyMap, dlls: &'c mut Vec<String>) -> Self {
+    pub fn new(
+        dependency_map: &'c mut DependencyMap,
+        dlls: &'c mut Vec<String>,
+        cache: Option<&'c mut ParseCache>,
+        modules: Option<&'c mut Modules>,
+        missing_includes: &'c mut Vec<(String, String)>,
+        unguarded_headers: &'c mut Vec<String>,
+        cli: &'c Cli<'c>,
+    ) -> Self {
         Self {
             dependency_map,
             dlls,
             seen: HashSet::new(),
+            cache,
+            modules,
+            missing_includes,
+            unguarded_headers,
+            uses_math: false,
+            cli,
+        }
+    }
+}
+
+impl Modules {
+    fn scan(&mut self, filename: &str, source: &str) {
+        for line in source
+            .lines()
+            .map(|l| l.trim().trim_end_matches(';').trim())
+        {
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["export", "module", name] => {
+                    self.interfaces
+                        .insert(name.to_string(), filename.to_string());
+                }
+                // Module implementation units implicitly import their module
+                ["module", name] | ["import", name] | ["export", "import", name]
+                    if !name.starts_with(&[':', '<', '"'][..]) =>
+                {
+                    self.imports
+                        .entry(filename.to_string())
+                        .or_default()
+                        .push(name.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Makes every file depend on the interface units of the modules it imports
+    fn add_dependencies(&self, dependency_map: &mut DependencyMap) {
+        for (file, imports) in &self.imports {
+            let interfaces = imports
+                .iter()
+                .filter_map(|module| self.interfaces.get(module))
+                .filter(|interface| *interface != file);
+
+            if let Some((dependencies, _)) = dependency_map.get_mut(file) {
+                for interface in interfaces {
+                    if !dependencies.contains(interface) {
+                        dependencies.push(interface.to_string());
+                    }
+                }
+            }
         }
     }
 }
 
 impl<'cli> Parser<'cli> {
     pub fn new(root_dir: PathBuf, cli: &'cli Cli<'cli>) -> Self {
+        // Includes are resolved to canonical paths relative to the root directory,
+        // so the root directory must be canonical as well (e.g. when reached through a symlink)
+        let root_dir = root_dir.canonicalize().unwrap_or(root_dir);
         Self { root_dir, cli }
     }
 
     pub fn parse(&self) -> Result<ParseResult, Box<dyn Error>> {
-        let mut dependency_map = HashMap::new();
+        if !self.cli.hash_cache {
+            return self.parse_with_cache(None);
+        }
+
+        let cache_file = self.root_dir.join(CACHE_FILE);
+        let mut cache = ParseCache::load(&cache_file, self.cache_fingerprint());
+        let result = self.parse_with_cache(Some(&mut cache))?;
+        cache.save(&cache_file)?;
+
+        Ok(result)
+    }
+
+    fn parse_with_cache(
+        &self,
+        mut cache: Option<&mut ParseCache>,
+    ) -> Result<ParseResult, Box<dyn Error>> {
+        let mut dependency_map = DependencyMap::new();
         let mut dlls = Vec::new();
+        let mut modules = Modules::default();
+        let mut missing_includes = Vec::new();
+        let mut unguarded_headers = Vec::new();
+        let mut uses_math = false;
+
+        for filename in self.source_files()? {
+            let mut ctx = ParseContext::new(
+                &mut dependency_map,
+                &mut dlls,
+                cache.as_deref_mut(),
+                Some(&mut modules).filter(|_| self.cli.modules),
+                &mut missing_includes,
+                &mut unguarded_headers,
+                self.cli,
+            );
+            read_file_and_get_include_files_recursively(&self.root_dir, &filename, &mut ctx)?;
+            uses_math |= ctx.uses_math;
+        }
+
+        // Including <math.h> for its macros or inline functions alone doesn't need libm
+        if self.cli.lazy_libm && !uses_math {
+            dlls.retain(|dll| dll != "m");
+        }
+
+        for library in &self.cli.libs {
+            if !dlls.iter().any(|dll| dll == library) {
+                dlls.push(library.to_string());
+            }
+        }
+
+        modules.add_dependencies(&mut dependency_map);
+        if self.cli.canonical_headers {
+            dependency_map = canonicalize_paths(dependency_map, &self.root_dir);
+        }
+        exclude_files(&mut dependency_map, &self.cli.exclude_files);
+
+        let mut result = ParseResult::new(dependency_map, dlls);
+        if self.cli.warn_missing {
+            result.warnings = missing_includes
+                .iter()
+                .map(|(file, include_file)| {
+                    format!("Could not find {} included from {}", include_file, file)
+                })
+                .collect();
+        }
+        for (file, source) in included_sources(&result.dependency_map, self.cli.source_extension())
+        {
+            result.warnings.push(format!(
+                "{} includes the source file {} directly, which may lead to duplicate symbols",
+                file, source
+            ));
+        }
+        result.missing_includes = missing_includes;
+        result.unguarded_headers = unguarded_headers;
+
+        Ok(result)
+    }
+
+    // Identifies the options that affect parsing, so a cache built with different ones isn't used
+    fn cache_fingerprint(&self) -> u64 {
+        let assignments = |pairs: Vec<(&str, &str)>| -> Vec<String> {
+            pairs
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect()
+        };
+
+        let mut link_map: Vec<_> = self
+            .cli
+            .link_map
+            .iter()
+            .map(|(header, library)| (header.as_str(), library.as_str()))
+            .collect();
+        link_map.sort();
+
+        // One line per option, the values of each separated by NULs
+        let options = [
+            vec![self.cli.source_extension().to_owned()],
+            self.cli.iquote.iter().map(|d| d.to_string()).collect(),
+            self.cli
+                .include_dirs
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            assignments(self.cli.include_macros.clone()),
+            self.cli.defines.iter().map(|d| d.to_string()).collect(),
+            assignments(link_map),
+        ];
+        let options: Vec<_> = options.iter().map(|values| values.join("\0")).collect();
 
+        content_hash(options.join("\n").as_bytes())
+    }
+
+    // Prints every #include line of every file reachable from the source files along with
+    // its classification and the path it resolves to
+    pub fn explain_includes(&self, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+        let mut seen = HashSet::new();
+        let mut pending = self.source_files()?;
+        pending.reverse();
+
+        while let Some(filename) = pending.pop() {
+            if !seen.insert(filename.clone()) {
+                continue;
+            }
+
+            let contents = fs::read_to_string(self.root_dir.join(&filename))?;
+            let contents = active_source(&contents, &self.cli.defines);
+            writeln!(out, "{}", filename.display())?;
+
+            for line in contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| line.starts_with("#include"))
+            {
+                match expand_include_macro(extract_include_filename(line), &self.cli.include_macros)
+                {
+                    IncludeFile::System(include_file) => {
+                        match resolve_system_include(
+                            &self.root_dir,
+                            include_file,
+                            &self.cli.include_dirs,
+                        ) {
+                            Some(resolved) => {
+                                writeln!(
+                                    out,
+                                    "    {} -> System, resolved to {} (found in an include directory)",
+                                    line,
+                                    resolved.display()
+                                )?;
+                                pending.push(resolved);
+                            }
+                            None => writeln!(out, "    {} -> System", line)?,
+                        }
+                    }
+                    IncludeFile::Macro(_) => writeln!(out, "    {} -> Unknown macro", line)?,
+                    IncludeFile::User(include_file) => {
+                        match resolve_include(
+                            &self.root_dir,
+                            &filename,
+                            include_file,
+                            &self.cli.quote_search_dirs(),
+                        ) {
+                            Some(resolved) => {
+                                writeln!(
+                                    out,
+                                    "    {} -> User, resolved to {} (found)",
+                                    line,
+                                    resolved.display()
+                                )?;
+                                pending.push(resolved);
+                            }
+                            None => match resolve_generated_include(
+                                &filename,
+                                include_file,
+                                &self.cli.quote_search_dirs(),
+                                &self.cli.generated,
+                            ) {
+                                Some(generated) => writeln!(
+                                    out,
+                                    "    {} -> User, resolved to {} (generated)",
+                                    line,
+                                    generated.display()
+                                )?,
+                                None => writeln!(
+                                    out,
+                                    "    {} -> User, resolved to {} (not found)",
+                                    line,
+                                    include_candidate(&filename, include_file).display()
+                                )?,
+                            },
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The --exclude path the path is, or lives under, if any
+    pub fn excluded_by(&self, path: &Path) -> Option<&'cli str> {
+        excluded_by(&self.root_dir, path, &self.cli.excludes)
+    }
+
+    // Whether the file (relative to the root directory) is parsed as a source file, judging by its extension
+    pub fn is_source_file(&self, path: &Path) -> bool {
+        let path = self.root_dir.join(path);
+
+        has_extension(&path, self.cli.source_extension())
+            || (self.cli.modules && is_module_interface(&path))
+            || (self.cli.detect_by_content
+                && path.extension().is_none()
+                && fs::read_to_string(&path)
+                    .map(|contents| looks_like_source(&contents))
+                    .unwrap_or(false))
+    }
+
+    // Returns the paths (relative to the root directory) of all the source files to parse
+    fn source_files(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
         let filter_criteria = |r: &Result<DirEntry, _>| {
             r.as_ref()
-                .map(|e| e.file_type().is_file() && has_extension(e.path(), self.cli.extension))
+                .map(|e| e.file_type().is_file() && self.is_source_file(e.path()))
                 .unwrap_or(false)
         };
 
-        let walker = WalkDir::new(&self.root_dir).into_iter();
-        for entry in walker
-            .filter_entry(|e| !is_hidden(e))
-            .filter(|r| filter_criteria(r))
-        {
-            if let Ok(entry) = entry {
-                let mut ctx = ParseContext::new(&mut dependency_map, &mut dlls);
+        // Paths stay relative to the root directory, so they are unambiguous across source roots
+        let source_roots = if self.cli.source_roots.is_empty() {
+            vec![self.root_dir.clone()]
+        } else {
+            self.cli
+                .source_roots
+                .iter()
+                .map(|dir| {
+                    self.root_dir
+                        .join(dir)
+                        .canonicalize()
+                        .ok()
+                        .filter(|path| path.starts_with(&self.root_dir))
+                        .ok_or_else(|| {
+                            format!("Source root {} is not a directory inside the project", dir)
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut source_files = Vec::new();
+        for source_root in source_roots {
+            let walker = WalkDir::new(source_root)
+                .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+                .into_iter();
+            for entry in walker
+                .filter_entry(|e| {
+                    e.depth() == 0 || !(is_hidden(e) || self.excluded_by(e.path()).is_some())
+                })
+                .filter(|r| filter_criteria(r))
+                .flatten()
+            {
                 let filename = entry.path().strip_prefix(&self.root_dir)?;
-                read_file_and_get_include_files_recursively(&self.root_dir, filename, &mut ctx)?;
+                if !source_files.iter().any(|f| f == filename) {
+                    source_files.push(filename.to_path_buf());
+                }
+            }
+        }
+
+        Ok(source_files)
+    }
+}
+
+// Whether a file without an extension is a C/C++ source, that is it includes other files.
+// Files starting with a shebang are scripts, even the ones run through a compiler (e.g. #!/usr/bin/tcc -run),
+// since the shebang itself doesn't compile
+fn looks_like_source(contents: &str) -> bool {
+    if contents.starts_with("#!") {
+        return false;
+    }
+
+    contents
+        .lines()
+        .any(|line| line.trim_start().starts_with("#include"))
+}
+
+// Removes the given files from the dependency map, both as entries and as dependencies of other files
+fn exclude_files(dependency_map: &mut DependencyMap, excluded: &[&str]) {
+    let excluded: Vec<_> = excluded
+        .iter()
+        .map(|f| f.trim_start_matches("./"))
+        .collect();
+
+    dependency_map.retain(|file, _| !excluded.contains(&file.as_str()));
+    for (dependencies, _) in dependency_map.values_mut() {
+        dependencies.retain(|d| !excluded.contains(&d.as_str()));
+    }
+}
+
+// Collapses the spellings of each file across the map (e.g. include/a.h and src/../include/a.h)
+// into its canonical path relative to the root directory, merging the entries that end up the same.
+// Files that don't exist (yet) are only normalized lexically
+fn canonicalize_paths(dependency_map: DependencyMap, root_dir: &Path) -> DependencyMap {
+    // The canonical paths of the files only start with the canonical root
+    let root_dir = root_dir
+        .canonicalize()
+        .unwrap_or_else(|_| root_dir.to_path_buf());
+    let canonical = |file: &str| {
+        root_dir
+            .join(file)
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.strip_prefix(&root_dir).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| normalize_lexically(Path::new(file)))
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    let mut canonicalized = DependencyMap::new();
+    for (file, (dependencies, has_main)) in dependency_map {
+        let entry = canonicalized
+            .entry(canonical(&file))
+            .or_insert_with(|| (Vec::new(), false));
+        entry.1 |= has_main;
+        for dependency in dependencies.iter().map(|d| canonical(d)) {
+            if !entry.0.contains(&dependency) {
+                entry.0.push(dependency);
             }
         }
+    }
+
+    canonicalized
+}
+
+// Finds the source files that are included directly by another file, along with the file including them
+pub fn included_sources(dependency_map: &DependencyMap, extension: &str) -> Vec<(String, String)> {
+    let mut included = Vec::new();
+    for (file, (dependencies, _)) in dependency_map {
+        for dependency in dependencies
+            .iter()
+            .filter(|d| *d != file && has_extension(d, extension))
+        {
+            included.push((file.to_owned(), dependency.to_owned()));
+        }
+    }
+    included
+}
+
+// The exclude path the path (either relative to the root directory or under it) is, or lives under, if any
+fn excluded_by<'e>(root_dir: &Path, path: &Path, excludes: &[&'e str]) -> Option<&'e str> {
+    let path = path.strip_prefix(root_dir).unwrap_or(path);
+    excludes
+        .iter()
+        .find(|excluded| path.starts_with(excluded.trim_start_matches("./")))
+        .copied()
+}
+
+// Detects whether the project under the root directory is a C or a C++ project, skipping the excluded paths
+pub fn detect_extension(
+    root_dir: &Path,
+    excludes: &[&str],
+) -> Result<&'static str, Box<dyn Error>> {
+    let (mut has_c, mut has_cpp) = (false, false);
 
-        Ok(ParseResult::new(dependency_map, dlls))
+    for entry in WalkDir::new(root_dir)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0 || !(is_hidden(e) || excluded_by(root_dir, e.path(), excludes).is_some())
+        })
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+    {
+        has_c |= has_extension(entry.path(), "c");
+        has_cpp |= has_extension(entry.path(), "cpp");
+    }
+
+    match (has_c, has_cpp) {
+        (true, false) => Ok("c"),
+        (false, true) => Ok("cpp"),
+        (true, true) => {
+            Err("Found both C and C++ source files, please choose one with --extension or build both with --mixed".into())
+        }
+        (false, false) => Err(
+            "Could not find any C or C++ source files, please provide one with --extension".into(),
+        ),
     }
 }
 
@@ -92,22 +586,28 @@ impl<'cli> Parser<'cli> {
 enum IncludeFile<'i> {
     System(&'i str),
     User(&'i str),
+    // An include of a macro's expansion, e.g. #include CONFIG_H
+    Macro(&'i str),
 }
 
 fn extract_include_filename(line: &str) -> IncludeFile<'_> {
     let (start_index, end_index) = (line.find('<'), line.find('>'));
 
     let mut is_system_file = true;
-    let (start_index, end_index) = if start_index.is_none() || end_index.is_none() {
-        let start_index = line.find('"').unwrap();
-        let start_pos = start_index + 1;
-        let end_index = line[start_pos..].find('"').unwrap();
-        let end_index = start_pos + end_index;
-        is_system_file = false;
-        (start_index, end_index)
-    } else {
-        (start_index.unwrap(), end_index.unwrap())
-    };
+    let (start_index, end_index) =
+        if let (Some(start_index), Some(end_index)) = (start_index, end_index) {
+            (start_index, end_index)
+        } else if !line.contains('"') {
+            let name = line.trim_start()["#include".len()..].trim();
+            return IncludeFile::Macro(name);
+        } else {
+            let start_index = line.find('"').unwrap();
+            let start_pos = start_index + 1;
+            let end_index = line[start_pos..].find('"').unwrap();
+            let end_index = start_pos + end_index;
+            is_system_file = false;
+            (start_index, end_index)
+        };
 
     let include_file = &line[(start_index + 1)..end_index];
 
@@ -118,53 +618,571 @@ fn extract_include_filename(line: &str) -> IncludeFile<'_> {
     }
 }
 
-fn get_include_files_and_update_dlls(source: &str, dlls: &mut Vec<String>) -> Vec<String> {
+// Replaces an include of a macro with the include the user told us the macro expands to.
+// Macros are given either as "file" or <file>, with the quotes of the former being optional
+fn expand_include_macro<'i>(
+    include_file: IncludeFile<'i>,
+    include_macros: &[(&'i str, &'i str)],
+) -> IncludeFile<'i> {
+    let name = match include_file {
+        IncludeFile::Macro(name) => name,
+        include_file => return include_file,
+    };
+
+    match include_macros
+        .iter()
+        .find(|(macro_name, _)| *macro_name == name)
+    {
+        Some((_, value)) if value.starts_with('<') && value.ends_with('>') => {
+            IncludeFile::System(&value[1..value.len() - 1])
+        }
+        Some((_, value)) => IncludeFile::User(value.trim_matches('"')),
+        None => include_file,
+    }
+}
+
+// Whether the header starts with an #ifndef/#define include guard or has #pragma once
+fn has_include_guard(contents: &str) -> bool {
+    let source = strip_comments(&join_continued_lines(contents));
+    // The directives with their whitespace normalized (e.g. "ifndef FOO_H"), None for any other line
+    let lines: Vec<Option<String>> = source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.strip_prefix('#')
+                .map(|directive| directive.split_whitespace().collect::<Vec<_>>().join(" "))
+        })
+        .collect();
+
+    if lines
+        .iter()
+        .any(|line| line.as_deref() == Some("pragma once"))
+    {
+        return true;
+    }
+
+    match (lines.first(), lines.get(1)) {
+        (Some(Some(condition)), Some(Some(define))) => {
+            let guard = condition
+                .strip_prefix("ifndef ")
+                .or_else(|| condition.strip_prefix("if !defined"))
+                .map(|name| name.trim_matches(['(', ')', ' ']));
+            let defined = define
+                .strip_prefix("define ")
+                .and_then(|rest| rest.split_whitespace().next());
+            guard.is_some() && guard == defined
+        }
+        _ => false,
+    }
+}
+
+// Whether the source appears to call one of the functions of the math library (or their float/long double variants)
+fn calls_math_function(contents: &str) -> bool {
+    const MATH_FUNCTIONS: &[&str] = &[
+        "acos",
+        "acosh",
+        "asin",
+        "asinh",
+        "atan",
+        "atan2",
+        "atanh",
+        "cbrt",
+        "ceil",
+        "copysign",
+        "cos",
+        "cosh",
+        "erf",
+        "erfc",
+        "exp",
+        "exp2",
+        "expm1",
+        "fabs",
+        "fdim",
+        "floor",
+        "fma",
+        "fmax",
+        "fmin",
+        "fmod",
+        "frexp",
+        "hypot",
+        "ilogb",
+        "ldexp",
+        "lgamma",
+        "llrint",
+        "llround",
+        "log",
+        "log10",
+        "log1p",
+        "log2",
+        "logb",
+        "lrint",
+        "lround",
+        "modf",
+        "nan",
+        "nearbyint",
+        "nextafter",
+        "pow",
+        "remainder",
+        "remquo",
+        "rint",
+        "round",
+        "scalbn",
+        "sin",
+        "sinh",
+        "sqrt",
+        "tan",
+        "tanh",
+        "tgamma",
+        "trunc",
+    ];
+
+    let source = strip_comments(contents);
+    let mut rest = source.as_str();
+    while let Some(start) =
+        rest.find(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '"' || c == '\'')
+    {
+        rest = &rest[start..];
+
+        // Skip string and character literals, the function names may appear in messages
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let literal = &rest[1..];
+            let mut escaped = false;
+            let end = literal
+                .find(|c| {
+                    let closes = c == quote && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map_or(literal.len(), |end| end + 1);
+            rest = &literal[end..];
+            continue;
+        }
+
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (identifier, after) = rest.split_at(end);
+        rest = after;
+
+        let base = identifier
+            .strip_suffix(['f', 'l'])
+            .filter(|base| !MATH_FUNCTIONS.contains(&identifier) && !base.is_empty())
+            .unwrap_or(identifier);
+        if MATH_FUNCTIONS.contains(&base) && after.trim_start().starts_with('(') {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Joins lines ending with a backslash with the next one, like the preprocessor does
+fn join_continued_lines(source: &str) -> String {
+    source.replace("\\\r\n", "").replace("\\\n", "")
+}
+
+// Replaces comments (including documentation blocks with example code) with a space
+// so that includes inside them aren't picked up. Newlines inside block comments are kept
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                stripped.push(c);
+                while let Some(next) = chars.next() {
+                    stripped.push(next);
+                    if next == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            stripped.push(escaped);
+                        }
+                    } else if next == c || next == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                stripped.push(' ');
+                while chars.next_if(|next| *next != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                stripped.push(' ');
+                chars.next();
+                let mut previous = '\0';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    if next == '\n' {
+                        stripped.push('\n');
+                    }
+                    previous = next;
+                }
+            }
+            c => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+// The source without the lines of the conditional branches the given defines rule out (comments stripped).
+// Conditions involving anything else can't be decided without the compiler, so their branches are kept
+fn active_source(source: &str, defines: &[&str]) -> String {
+    // For every open conditional: whether the current branch is taken and whether an earlier one was
+    let mut conditionals: Vec<(Option<bool>, Option<bool>)> = Vec::new();
+    let mut active = String::new();
+
+    for line in strip_comments(&join_continued_lines(source)).lines() {
+        let directive = line
+            .trim_start()
+            .strip_prefix('#')
+            .map(|directive| directive.trim_start())
+            .unwrap_or("");
+        let (keyword, condition) = directive
+            .split_once(char::is_whitespace)
+            .map(|(keyword, condition)| (keyword, condition.trim()))
+            .unwrap_or((directive, ""));
+
+        match keyword {
+            "if" | "ifdef" | "ifndef" => {
+                let taken = match keyword {
+                    "ifdef" => evaluate_defined(condition, defines),
+                    "ifndef" => evaluate_defined(condition, defines).map(|defined| !defined),
+                    _ => evaluate_condition(condition, defines),
+                };
+                conditionals.push((taken, taken));
+                continue;
+            }
+            "elif" | "else" => {
+                if let Some((taken, earlier)) = conditionals.last_mut() {
+                    let condition = if keyword == "else" {
+                        Some(true)
+                    } else {
+                        evaluate_condition(condition, defines)
+                    };
+                    *taken = match (*earlier, condition) {
+                        (Some(true), _) | (_, Some(false)) => Some(false),
+                        (Some(false), condition) => condition,
+                        (None, _) => None,
+                    };
+                    *earlier = match (*earlier, condition) {
+                        (Some(true), _) | (_, Some(true)) => Some(true),
+                        (Some(false), Some(false)) => Some(false),
+                        _ => None,
+                    };
+                }
+                continue;
+            }
+            "endif" => {
+                conditionals.pop();
+                continue;
+            }
+            _ => {}
+        }
+
+        if conditionals.iter().all(|(taken, _)| *taken != Some(false)) {
+            active.push_str(line);
+        }
+        active.push('\n');
+    }
+
+    active
+}
+
+// The value of the macro if it's one of the defines, "1" for the ones given without a value
+fn define_value<'d>(name: &str, defines: &[&'d str]) -> Option<&'d str> {
+    defines
+        .iter()
+        .find_map(|define| match define.split_once('=') {
+            Some((define, value)) if define == name => Some(value),
+            None if *define == name => Some("1"),
+            _ => None,
+        })
+}
+
+// Only the defines are known to be defined, so a macro that isn't one of them may still be
+fn evaluate_defined(name: &str, defines: &[&str]) -> Option<bool> {
+    define_value(name.trim(), defines).map(|_| true)
+}
+
+// Evaluates the simple #if conditions: integers, defined(NAME), the value of a define and their negations
+fn evaluate_condition(condition: &str, defines: &[&str]) -> Option<bool> {
+    let condition = condition.trim();
+    if let Some(negated) = condition.strip_prefix('!') {
+        return evaluate_condition(negated, defines).map(|value| !value);
+    }
+
+    if let Some(name) = condition.strip_prefix("defined") {
+        return evaluate_defined(
+            name.trim().trim_start_matches('(').trim_end_matches(')'),
+            defines,
+        );
+    }
+
+    if let Ok(value) = condition.parse::<i64>() {
+        return Some(value != 0);
+    }
+
+    define_value(condition, defines)
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .map(|value| value != 0)
+}
+
+fn get_include_files_and_update_dlls(
+    source: &str,
+    dlls: &mut Vec<String>,
+    include_macros: &[(&str, &str)],
+    link_map: &HashMap<String, String>,
+) -> Vec<String> {
     let mut include_files = Vec::new();
-    source
+    strip_comments(&join_continued_lines(source))
         .lines()
         .filter(|line| line.trim_start().starts_with("#include"))
         .for_each(|line| {
-            let include_file = extract_include_filename(line);
+            let include_file = expand_include_macro(extract_include_filename(line), include_macros);
             match include_file {
                 IncludeFile::System(include_file) => {
-                    if DLL_MAP.contains_key(include_file) {
-                        let linkage_name = DLL_MAP.get(include_file).unwrap().to_string();
+                    // The user's mappings take precedence over the default ones
+                    let linkage_name = link_map
+                        .get(include_file)
+                        .map(String::as_str)
+                        .or_else(|| DLL_MAP.get(include_file).copied());
+                    if let Some(linkage_name) = linkage_name.map(str::to_owned) {
                         if !dlls.contains(&linkage_name) {
                             dlls.push(linkage_name);
                         }
                     }
                 }
+                // Windows accepts backslashes as separators, the other platforms don't
                 IncludeFile::User(include_file) => {
-                    include_files.push(include_file.to_string());
+                    include_files.push(include_file.replace('\\', "/"));
                 }
+                // Without knowing what the macro expands to there's nothing to track
+                IncludeFile::Macro(_) => {}
             }
         });
 
     include_files
 }
 
+fn get_system_include_files(source: &str, include_macros: &[(&str, &str)]) -> Vec<String> {
+    strip_comments(&join_continued_lines(source))
+        .lines()
+        .filter(|line| line.trim_start().starts_with("#include"))
+        .filter_map(|line| {
+            match expand_include_macro(extract_include_filename(line), include_macros) {
+                IncludeFile::System(include_file) => Some(include_file.to_owned()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// The path of a user include relative to the root directory, as written in the source
+fn include_candidate(filename: &Path, include_file: &str) -> PathBuf {
+    let mut candidate = filename.to_path_buf();
+    candidate.pop();
+    candidate.push(include_file);
+    candidate
+}
+
+// Resolves a user include of the given file to its canonical path relative to the root directory.
+// Like the compiler, it looks next to the including file first and then in the search directories
+fn resolve_include(
+    root_dir: &Path,
+    filename: &Path,
+    include_file: &str,
+    search_dirs: &[&str],
+) -> Option<PathBuf> {
+    let candidate = std::iter::once(include_candidate(filename, include_file))
+        .chain(
+            search_dirs
+                .iter()
+                .map(|dir| Path::new(dir).join(include_file)),
+        )
+        .find(|candidate| root_dir.join(candidate).exists())?;
+
+    // Absolute search directories are used as they are, as they may well be outside the project
+    if candidate.is_absolute() {
+        return Some(candidate);
+    }
+
+    root_dir
+        .join(candidate)
+        .canonicalize()
+        .ok()?
+        .strip_prefix(root_dir)
+        .ok()
+        .map(|p| p.to_path_buf())
+}
+
+// Resolves an angle bracket include against the include directories only, like the compiler does
+fn resolve_system_include(
+    root_dir: &Path,
+    include_file: &str,
+    include_dirs: &[&str],
+) -> Option<PathBuf> {
+    let full_path = include_dirs
+        .iter()
+        .map(|dir| root_dir.join(dir).join(include_file))
+        .find_map(|candidate| candidate.canonicalize().ok())?;
+
+    full_path
+        .strip_prefix(root_dir)
+        .ok()
+        .map(|p| p.to_path_buf())
+}
+
+// Generated files may not exist yet while parsing, so an include naming one of them
+// resolves to its declared name, where the compiler will find it once it's generated
+fn resolve_generated_include(
+    filename: &Path,
+    include_file: &str,
+    search_dirs: &[&str],
+    generated: &[(&str, &str)],
+) -> Option<PathBuf> {
+    std::iter::once(include_candidate(filename, include_file))
+        .chain(
+            search_dirs
+                .iter()
+                .map(|dir| Path::new(dir).join(include_file)),
+        )
+        .map(|candidate| normalize_lexically(&candidate))
+        .find(|candidate| {
+            generated
+                .iter()
+                .any(|(file, _)| normalize_lexically(Path::new(file)) == *candidate)
+        })
+}
+
+// Removes the . and .. components without touching the filesystem
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 fn read_file_and_get_include_files_recursively(
     root_dir: &PathBuf,
     filename: &Path,
     ctx: &mut ParseContext,
 ) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(filename)?;
-    let has_main = contents.contains("main(");
-    let mut include_files = get_include_files_and_update_dlls(&contents, ctx.dlls);
-
-    for include_file in &mut include_files {
-        let mut full_path = root_dir.to_path_buf();
-        full_path.push(filename);
-        full_path.pop();
-        full_path.push(&include_file);
-        full_path = full_path.canonicalize()?;
-
-        *include_file = full_path
-            .strip_prefix(root_dir)?
-            .to_str()
-            .unwrap()
-            .to_string();
+    // Only the generated files that haven't been generated yet are missing, there's nothing to read in them
+    if !root_dir.join(filename).exists() {
+        ctx.dependency_map
+            .entry(filename.to_str().unwrap().to_string())
+            .or_insert((Vec::new(), false));
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(root_dir.join(filename))?;
+    if let Some(modules) = ctx.modules.as_mut() {
+        modules.scan(filename.to_str().unwrap(), &contents);
+    }
+
+    let name = filename.to_str().unwrap();
+    let is_header = !has_extension(name, ctx.cli.source_extension()) && !is_module_interface(name);
+    if is_header && !has_include_guard(&contents) {
+        ctx.unguarded_headers.push(name.to_string());
+    }
 
+    if ctx.cli.lazy_libm && !ctx.uses_math {
+        ctx.uses_math = calls_math_function(&contents);
+    }
+
+    let hash = content_hash(contents.as_bytes());
+    let cached = ctx
+        .cache
+        .as_mut()
+        .and_then(|cache| cache.lookup(root_dir, filename.to_str().unwrap(), hash));
+
+    let (include_files, has_main, file_dlls) = match cached {
+        Some(entry) => (entry.include_files, entry.has_main, entry.dlls),
+        None => {
+            let has_main = contents.contains("main(");
+            let mut file_dlls = Vec::new();
+            let mut include_files = Vec::new();
+            let mut has_missing_includes = false;
+
+            // Unresolved includes are skipped and reported after parsing
+            let active = active_source(&contents, &ctx.cli.defines);
+            let include_files_found = get_include_files_and_update_dlls(
+                &active,
+                &mut file_dlls,
+                &ctx.cli.include_macros,
+                &ctx.cli.link_map,
+            );
+            let search_dirs = ctx.cli.quote_search_dirs();
+            for include_file in include_files_found {
+                let resolved = resolve_include(root_dir, filename, &include_file, &search_dirs)
+                    .or_else(|| {
+                        resolve_generated_include(
+                            filename,
+                            &include_file,
+                            &search_dirs,
+                            &ctx.cli.generated,
+                        )
+                    });
+                match resolved {
+                    Some(full_path) => include_files.push(full_path.to_str().unwrap().to_string()),
+                    None => {
+                        has_missing_includes = true;
+                        let filename = filename.to_str().unwrap().to_string();
+                        if !ctx
+                            .missing_includes
+                            .contains(&(filename.clone(), include_file.clone()))
+                        {
+                            ctx.missing_includes.push((filename, include_file));
+                        }
+                    }
+                }
+            }
+
+            // Angle bracket includes found in the include directories belong to the project,
+            // the rest are system headers
+            if !ctx.cli.include_dirs.is_empty() {
+                for include_file in get_system_include_files(&active, &ctx.cli.include_macros) {
+                    if let Some(full_path) =
+                        resolve_system_include(root_dir, &include_file, &ctx.cli.include_dirs)
+                    {
+                        include_files.push(full_path.to_str().unwrap().to_string());
+                    }
+                }
+            }
+
+            // Files with unresolved includes aren't cached so they are reported on every run
+            if let Some(cache) = ctx.cache.as_mut().filter(|_| !has_missing_includes) {
+                let entry = CacheEntry {
+                    hash,
+                    has_main,
+                    include_files: include_files.clone(),
+                    dlls: file_dlls.clone(),
+                };
+                cache.insert(filename.to_str().unwrap().to_string(), entry);
+            }
+
+            (include_files, has_main, file_dlls)
+        }
+    };
+
+    for dll in file_dlls {
+        if !ctx.dlls.contains(&dll) {
+            ctx.dlls.push(dll);
+        }
+    }
+
+    for include_file in &include_files {
         if !ctx.dependency_map.contains_key(include_file) && !ctx.seen.contains(include_file) {
             ctx.seen.insert(include_file.to_string());
             read_file_and_get_include_files_recursively(root_dir, Path::new(include_file), ctx)?;
@@ -183,6 +1201,16 @@ fn read_file_and_get_include_files_recursively(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cli;
+    use std::fs;
+
+    fn write_files(root_dir: &Path, files: &[(&str, &str)]) {
+        for (filename, contents) in files {
+            let path = root_dir.join(filename);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
 
     #[test]
     fn extract_include_filename_works() {
@@ -214,6 +1242,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn include_macros_are_expanded() {
+        let source = r##"
+            #include CONFIG_H
+            #include PLATFORM_MATH
+            #include UNKNOWN_H
+        "##;
+
+        let mut dlls = Vec::new();
+        let include_files = get_include_files_and_update_dlls(
+            source,
+            &mut dlls,
+            &[("CONFIG_H", "\"config.h\""), ("PLATFORM_MATH", "<math.h>")],
+            &HashMap::new(),
+        );
+
+        assert_eq!(include_files, vec!["config.h"]);
+        assert_eq!(dlls, vec!["m"]);
+    }
+
     #[test]
     fn get_include_files_and_update_dlls_works() {
         let source = r##"
@@ -230,9 +1278,837 @@ mod tests {
         "##;
 
         let mut dlls = Vec::new();
-        let include_files = get_include_files_and_update_dlls(source, &mut dlls);
+        let include_files =
+            get_include_files_and_update_dlls(source, &mut dlls, &[], &HashMap::new());
 
         assert_eq!(include_files, vec!["my_header.h", "string_interning.h"]);
         assert_eq!(dlls, vec!["m", "pthread"]);
     }
+
+    #[test]
+    fn continued_include_is_detected() {
+        let source = "#include \\\n    \"foo.h\"\n#include <math.h>\\\r\n\nint x;\n";
+
+        let mut dlls = Vec::new();
+        let include_files =
+            get_include_files_and_update_dlls(source, &mut dlls, &[], &HashMap::new());
+
+        assert_eq!(include_files, vec!["foo.h"]);
+        assert_eq!(dlls, vec!["m"]);
+    }
+
+    #[test]
+    fn includes_inside_comments_are_ignored() {
+        let source = r##"
+            /**
+             * Example usage:
+             * @code
+             * #include "example.h"
+             * @endcode
+             */
+            #include "real.h" // #include "trailing.h"
+            // #include "commented.h"
+            /* #include <math.h> */ #include "after_comment.h"
+            const char *s = "/* not a comment */";
+            #include "last.h"
+        "##;
+
+        let mut dlls = Vec::new();
+        let include_files =
+            get_include_files_and_update_dlls(source, &mut dlls, &[], &HashMap::new());
+
+        assert_eq!(include_files, vec!["real.h", "after_comment.h", "last.h"]);
+        assert!(dlls.is_empty());
+    }
+
+    #[test]
+    fn explain_includes_reports_classification() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "main.c",
+                    "#include <stdio.h>\n#include \"util.h\"\n#include \"missing.h\"\n",
+                ),
+                ("util.h", "int util(void);\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let parser = Parser::new(root_dir.path().to_path_buf(), &cli);
+
+        let mut out = Vec::new();
+        parser.explain_includes(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("main.c\n"));
+        assert!(out.contains("#include <stdio.h> -> System"));
+        assert!(out.contains("#include \"util.h\" -> User, resolved to util.h (found)"));
+        assert!(out.contains("#include \"missing.h\" -> User, resolved to missing.h (not found)"));
+        assert!(out.contains("util.h\n"));
+    }
+
+    #[test]
+    fn detect_extension_works() {
+        let c_root = tempfile::tempdir().unwrap();
+        write_files(
+            c_root.path(),
+            &[("main.c", ""), ("src/util.c", ""), ("util.h", "")],
+        );
+        assert_eq!(detect_extension(c_root.path(), &[]).unwrap(), "c");
+
+        let cpp_root = tempfile::tempdir().unwrap();
+        write_files(cpp_root.path(), &[("main.cpp", ""), ("src/util.cpp", "")]);
+        assert_eq!(detect_extension(cpp_root.path(), &[]).unwrap(), "cpp");
+
+        let mixed_root = tempfile::tempdir().unwrap();
+        write_files(mixed_root.path(), &[("main.c", ""), ("src/util.cpp", "")]);
+        assert!(detect_extension(mixed_root.path(), &[])
+            .unwrap_err()
+            .to_string()
+            .contains("--extension"));
+        assert_eq!(
+            detect_extension(mixed_root.path(), &["./src"]).unwrap(),
+            "c"
+        );
+    }
+
+    #[test]
+    fn hash_cache_serves_unchanged_files() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "main.c",
+                    "#include <math.h>\n#include \"util.h\"\nint main() {}\n",
+                ),
+                ("util.h", "int util(void);\n"),
+            ],
+        );
+
+        let matches =
+            cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c", "--hash-cache"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let parser = Parser::new(root_dir.path().to_path_buf(), &cli);
+        let first = parser.parse().unwrap();
+
+        // Rewrite the file with the exact same contents
+        write_files(
+            root_dir.path(),
+            &[(
+                "main.c",
+                "#include <math.h>\n#include \"util.h\"\nint main() {}\n",
+            )],
+        );
+
+        let cache_file = root_dir.path().join(CACHE_FILE);
+        let mut cache = ParseCache::load(&cache_file, parser.cache_fingerprint());
+        let second = parser.parse_with_cache(Some(&mut cache)).unwrap();
+
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(first.dependency_map, second.dependency_map);
+        assert_eq!(second.dlls, vec!["m"]);
+    }
+
+    #[test]
+    fn strict_includes_lists_unresolved_includes() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include \"util.h\"\n#include \"missing.h\"\n"),
+                ("util.h", "#include \"gone.h\"\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--strict-includes",
+            "--warn-missing",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.dependency_map["main.c"].0, vec!["util.h"]);
+        assert_eq!(
+            result.warnings,
+            vec![
+                "Could not find missing.h included from main.c",
+                "Could not find gone.h included from util.h"
+            ]
+        );
+
+        let error = result.ensure_includes_resolved().unwrap_err().to_string();
+        assert!(error.contains("    missing.h included from main.c"));
+        assert!(error.contains("    gone.h included from util.h"));
+    }
+
+    #[test]
+    fn quoted_includes_resolve_via_absolute_iquote_dirs() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let shared_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[("main.c", "#include \"shared.h\"\nint main() {}\n")],
+        );
+        write_files(shared_dir.path(), &[("shared.h", "int shared(void);\n")]);
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--iquote",
+            shared_dir.path().to_str().unwrap(),
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        let shared_header = shared_dir.path().join("shared.h");
+        assert_eq!(
+            result.dependency_map["main.c"].0,
+            vec![shared_header.to_str().unwrap()]
+        );
+        assert!(result.missing_includes.is_empty());
+    }
+
+    #[test]
+    fn quoted_includes_resolve_via_iquote_dirs() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "src/main.c",
+                    "#include \"util.h\"\n#include \"local.h\"\nint main() {}\n",
+                ),
+                ("src/local.h", "int local(void);\n"),
+                ("include/util.h", "int util(void);\n"),
+                ("include/local.h", "int shadowed(void);\n"),
+            ],
+        );
+
+        let matches =
+            cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c", "--iquote", "include"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        // The directory of the including file comes before the iquote directories
+        assert_eq!(
+            result.dependency_map["src/main.c"].0,
+            vec!["include/util.h", "src/local.h"]
+        );
+        assert!(result.missing_includes.is_empty());
+    }
+
+    #[test]
+    fn include_spellings_resolve_to_one_path() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("a.c", "#include \"./util/x.h\"\n"),
+                ("b.c", "#include \"util/x.h\"\n"),
+                ("util/c.c", "#include \"../util/./x.h\"\n"),
+                ("util/x.h", "int x(void);\n"),
+            ],
+        );
+
+        let parse = |root_dir: &Path| {
+            let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+            let cli = Cli::from_matches(&matches, root_dir).unwrap();
+            Parser::new(root_dir.to_path_buf(), &cli).parse().unwrap()
+        };
+
+        let result = parse(root_dir.path());
+        for file in &["a.c", "b.c", "util/c.c"] {
+            assert_eq!(result.dependency_map[*file].0, vec!["util/x.h"]);
+        }
+        assert_eq!(
+            result.dependency_map.keys().collect::<Vec<_>>(),
+            vec!["a.c", "b.c", "util/c.c", "util/x.h"]
+        );
+
+        // The same holds when the root directory is reached through a symlink
+        #[cfg(unix)]
+        {
+            let link_dir = tempfile::tempdir().unwrap();
+            let link = link_dir.path().join("project");
+            std::os::unix::fs::symlink(root_dir.path(), &link).unwrap();
+            assert_eq!(parse(&link).dependency_map, result.dependency_map);
+        }
+    }
+
+    #[test]
+    fn excluded_files_are_removed() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include \"util.h\"\nint main() {}\n"),
+                ("util.h", "int util(void);\n"),
+                (
+                    "tests/scratch_test.c",
+                    "#include \"../util.h\"\nint main() {}\n",
+                ),
+                (
+                    "tests/util_test.c",
+                    "#include \"../util.h\"\nint main() {}\n",
+                ),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--exclude-file",
+            "./tests/scratch_test.c",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.dependency_map.keys().collect::<Vec<_>>(),
+            vec!["main.c", "tests/util_test.c", "util.h"]
+        );
+    }
+
+    #[test]
+    fn multiple_source_roots_are_merged() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("libfoo/foo.c", "#include \"foo.h\"\n"),
+                ("libfoo/foo.h", "int foo(void);\n"),
+                ("libfoo/util.c", "int util(void) { return 0; }\n"),
+                (
+                    "app/main.c",
+                    "#include \"../libfoo/foo.h\"\nint main() {}\n",
+                ),
+                ("app/util.c", "int app_util(void) { return 0; }\n"),
+                ("scratch/main.c", "int main() {}\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--source-root",
+            "libfoo",
+            "app",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.dependency_map.keys().collect::<Vec<_>>(),
+            vec![
+                "app/main.c",
+                "app/util.c",
+                "libfoo/foo.c",
+                "libfoo/foo.h",
+                "libfoo/util.c"
+            ]
+        );
+        assert_eq!(result.dependency_map["app/main.c"].0, vec!["libfoo/foo.h"]);
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--source-root",
+            "missing",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        assert!(Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .is_err());
+    }
+
+    #[test]
+    fn include_macro_dependency_is_tracked() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include CONFIG_H\nint main() {}\n"),
+                ("config.h", "#define DEBUG 1\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--include-macro",
+            "CONFIG_H=\"config.h\"",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.dependency_map["main.c"].0, vec!["config.h"]);
+    }
+
+    #[test]
+    fn link_map_adds_libraries() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "main.c",
+                    "#include <foo.h>\n#include <math.h>\nint main() {}\n",
+                ),
+                ("links.toml", "foo.h = \"foo\"\n\"math.h\" = \"openlibm\"\n"),
+            ],
+        );
+
+        let link_map_file = root_dir.path().join("links.toml");
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--link-map",
+            link_map_file.to_str().unwrap(),
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.dlls, vec!["foo", "openlibm"]);
+    }
+
+    #[test]
+    fn included_sources_are_reported() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include \"impl.c\"\nint main() {}\n"),
+                ("impl.c", "int answer() { return 42; }\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.warnings,
+            vec!["main.c includes the source file impl.c directly, which may lead to duplicate symbols"]
+        );
+    }
+
+    #[test]
+    fn headers_without_guards_are_listed() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "main.c",
+                    "#include \"guarded.h\"\n#include \"once.h\"\n#include \"bare.h\"\n",
+                ),
+                (
+                    "guarded.h",
+                    "/* License */\n#ifndef GUARDED_H\n# define GUARDED_H\nint f();\n#endif\n",
+                ),
+                ("once.h", "#pragma once\nint g();\n"),
+                ("bare.h", "// #pragma once\nint h();\n"),
+            ],
+        );
+
+        let matches =
+            cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c", "--require-guards"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.unguarded_headers, vec!["bare.h"]);
+        let error = result.ensure_headers_guarded().unwrap_err().to_string();
+        assert!(error.ends_with(":\n    bare.h"));
+    }
+
+    #[test]
+    fn lazy_libm_needs_a_math_call() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "main.c",
+                    "#include <math.h>\n#include \"util.h\"\nint main() { return isnan(NAN); }\n",
+                ),
+                (
+                    "util.h",
+                    "/* sqrt(2) */\nstatic const char *sqrt_name = \"sqrt(\";\n",
+                ),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let matches =
+                cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"].iter().chain(args));
+            let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+            Parser::new(root_dir.path().to_path_buf(), &cli)
+                .parse()
+                .unwrap()
+                .dlls
+        };
+
+        assert_eq!(parse(&[]), vec!["m"]);
+        assert!(parse(&["--lazy-libm"]).is_empty());
+
+        fs::write(
+            root_dir.path().join("util.h"),
+            "static inline float root(float x) { return sqrtf (x); }\n",
+        )
+        .unwrap();
+        assert_eq!(parse(&["--lazy-libm"]), vec!["m"]);
+    }
+
+    #[test]
+    fn extensionless_sources_are_detected_by_content() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include \"util.h\"\nint main() {}\n"),
+                ("util.h", "int util();\n"),
+                (
+                    "tool",
+                    "#include <stdio.h>\nint main() { puts(\"tool\"); }\n",
+                ),
+                (
+                    "script",
+                    "#!/usr/bin/tcc -run\n#include <stdio.h>\nint main() {}\n",
+                ),
+                ("build", "#!/bin/sh\n#include is not a shell command\n"),
+                ("LICENSE", "Copyright\n"),
+            ],
+        );
+
+        let parse = |args: &[&str]| {
+            let matches =
+                cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"].iter().chain(args));
+            let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+            let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+                .parse()
+                .unwrap();
+            result.dependency_map.keys().cloned().collect::<Vec<_>>()
+        };
+
+        assert_eq!(parse(&[]), vec!["main.c", "util.h"]);
+        assert_eq!(
+            parse(&["--detect-by-content"]),
+            vec!["main.c", "tool", "util.h"]
+        );
+
+        // The detected source has to compile as C, which is how the Makefile builds it
+        if let Ok(status) = std::process::Command::new("cc")
+            .args(["-x", "c", "-fsyntax-only", "tool"])
+            .current_dir(root_dir.path())
+            .status()
+        {
+            assert!(status.success());
+        }
+    }
+
+    #[test]
+    fn warnings_are_truncated_after_max_warnings() {
+        let mut result = ParseResult::new(DependencyMap::new(), Vec::new());
+        result.warnings = (1..=5).map(|i| format!("warning {}", i)).collect();
+
+        assert_eq!(
+            result.displayed_warnings(Some(2)),
+            vec!["warning 1", "warning 2", "… and 3 more"]
+        );
+        assert_eq!(result.displayed_warnings(Some(5)), result.warnings);
+        assert_eq!(result.displayed_warnings(None), result.warnings);
+    }
+
+    #[test]
+    fn backslash_separated_include_resolves() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "#include \"sub\\header.h\"\nint main() {}\n"),
+                ("sub/header.h", "int f();\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from(["makegen", "-b", "foo", "-e", "c"]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.dependency_map["main.c"].0, vec!["sub/header.h"]);
+        assert!(result.missing_includes.is_empty());
+    }
+
+    #[test]
+    fn generated_header_is_recorded_before_it_exists() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "src/main.c",
+                    "#include \"config.h\"\n#include \"../gen/version.h\"\nint main() {}\n",
+                ),
+                // Resolves back to the generated header through the iquote directory
+                ("src/config.h", "#include \"version.h\"\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--iquote",
+            "gen",
+            "--generated",
+            "./gen/version.h=./gen_version.sh > gen/version.h",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.dependency_map["src/main.c"].0,
+            vec!["src/config.h", "gen/version.h"]
+        );
+        assert_eq!(
+            result.dependency_map["src/config.h"].0,
+            vec!["gen/version.h"]
+        );
+        assert_eq!(result.dependency_map["gen/version.h"], (Vec::new(), false));
+        assert!(result.missing_includes.is_empty());
+    }
+
+    #[test]
+    fn canonical_headers_collapse_spellings() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[("src/main.c", ""), ("src/util.c", ""), ("include/a.h", "")],
+        );
+        // A spelling of the root that isn't canonical
+        let root_dir = root_dir.path().join("src").join("..");
+
+        let mut dependency_map = DependencyMap::new();
+        dependency_map.insert(
+            "src/main.c".to_owned(),
+            (
+                vec!["include/a.h".to_owned(), "src/../include/a.h".to_owned()],
+                true,
+            ),
+        );
+        dependency_map.insert(
+            "src/util.c".to_owned(),
+            (vec!["./include/./a.h".to_owned()], false),
+        );
+        dependency_map.insert("include/a.h".to_owned(), (Vec::new(), false));
+        dependency_map.insert("src/../include/a.h".to_owned(), (Vec::new(), false));
+
+        let dependency_map = canonicalize_paths(dependency_map, &root_dir);
+
+        assert_eq!(
+            dependency_map.keys().collect::<Vec<_>>(),
+            vec!["include/a.h", "src/main.c", "src/util.c"]
+        );
+        assert_eq!(dependency_map["src/main.c"].0, vec!["include/a.h"]);
+        assert_eq!(dependency_map["src/util.c"].0, vec!["include/a.h"]);
+
+        // A symlinked header collapses into its target, even when the root directory is reached through a symlink
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("a.h", root_dir.join("include/alias.h")).unwrap();
+            let link_dir = tempfile::tempdir().unwrap();
+            let link = link_dir.path().join("project");
+            std::os::unix::fs::symlink(&root_dir, &link).unwrap();
+
+            let mut dependency_map = DependencyMap::new();
+            dependency_map.insert(
+                "src/main.c".to_owned(),
+                (vec!["include/alias.h".to_owned()], true),
+            );
+            let dependency_map = canonicalize_paths(dependency_map, &link);
+
+            assert_eq!(dependency_map["src/main.c"].0, vec!["include/a.h"]);
+        }
+    }
+
+    #[test]
+    fn includes_resolve_via_include_dirs() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                (
+                    "src/main.c",
+                    "#include <stdio.h>\n#include <proj/api.h>\n#include \"config.h\"\nint main() {}\n",
+                ),
+                ("include/proj/api.h", "int api(void);\n"),
+                ("include/config.h", "#define X 1\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--include-dir",
+            "include",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.dependency_map["src/main.c"].0,
+            vec!["include/config.h", "include/proj/api.h"]
+        );
+        assert!(result.dependency_map.contains_key("include/proj/api.h"));
+        assert!(result.missing_includes.is_empty());
+    }
+
+    #[test]
+    fn excluded_paths_are_not_scanned() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[
+                ("main.c", "int main() {}\n"),
+                ("legacy.c", "int legacy(void) { return 0; }\n"),
+                ("build/gen.c", "int gen(void) { return 0; }\n"),
+                (
+                    "third_party/zlib/inflate.c",
+                    "int inflate(void) { return 0; }\n",
+                ),
+                ("src/third_party.c", "int third_party(void) { return 0; }\n"),
+            ],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen",
+            "-b",
+            "foo",
+            "-e",
+            "c",
+            "--exclude",
+            "build/",
+            "./third_party",
+            "legacy.c",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            result.dependency_map.keys().collect::<Vec<_>>(),
+            vec!["main.c", "src/third_party.c"]
+        );
+    }
+
+    #[test]
+    fn libs_are_merged_with_detected_libraries() {
+        let root_dir = tempfile::tempdir().unwrap();
+        write_files(
+            root_dir.path(),
+            &[("main.c", "#include <pthread.h>\nint main() {}\n")],
+        );
+
+        let matches = cli::app().get_matches_from([
+            "makegen", "-b", "foo", "-e", "c", "--libs", "ssl", "pthread", "crypto",
+        ]);
+        let cli = Cli::from_matches(&matches, root_dir.path()).unwrap();
+        let result = Parser::new(root_dir.path().to_path_buf(), &cli)
+            .parse()
+            .unwrap();
+
+        assert_eq!(result.dlls, vec!["pthread", "ssl", "crypto"]);
+    }
+
+    #[test]
+    fn defines_rule_out_conditional_includes() {
+        let source = r##"
+            #ifdef NDEBUG
+            #include "release.h"
+            #else
+            #include "debug.h"
+            #endif
+            #if FOO == 2
+            #include "unknown.h"
+            #elif defined(FOO)
+            #include "foo.h"
+            #else
+            #include "no_foo.h"
+            #endif
+            #if !FOO
+            #include "not_foo.h"
+            #endif
+            #ifdef __linux__
+            #include "linux.h"
+            #endif
+            #if 0
+            #include "disabled.h"
+            #endif
+        "##;
+
+        let active = active_source(source, &["NDEBUG", "FOO=1"]);
+        let include_files =
+            get_include_files_and_update_dlls(&active, &mut Vec::new(), &[], &HashMap::new());
+
+        assert_eq!(
+            include_files,
+            vec!["release.h", "unknown.h", "foo.h", "linux.h"]
+        );
+    }
 }